@@ -1,83 +1,842 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::math::*;
-use gomez::nalgebra::{Dyn, IsContiguous};
+use crate::params::{Expr, ParamError, Parameters};
+use gomez::nalgebra::{DMatrix, DVector, Dyn, IsContiguous};
 use gomez::{Domain, Problem, SolverDriver, System};
+use kurbo::Point;
+
+// Structure of a system: the vertices whose coordinates are the variables, the
+// bindings giving the equations and where their jacobians go. It only changes
+// with the set of driving bindings and bounded vertices, not with their values or
+// positions, and Sketch keeps it between the solves of a component
+#[derive(Clone, Debug)]
+pub struct Layout {
+    // Vertices of the variables, sorted by id, x then y, so identical sketches
+    // give identical variables
+    v_ids: Vec<VertexId>,
+    inv_lut: BTreeMap<VertexId, usize>,
+    // The driving bindings, sorted by id, which is the order of the equations
+    bind_ids: Vec<BindingId>,
+    // Columns of the values each binding is evaluated on, x and y of its vertices
+    columns: Vec<Vec<usize>>,
+    // Index of the slack of each inequality, the slacks being stored after the
+    // coordinates
+    inv_slack: BTreeMap<BindingId, usize>,
+    // Basis of the null space of the jacobian found by the last completion, see
+    // complete_free_dofs
+    null_space: Option<DMatrix<f64>>,
+}
+impl Layout {
+    // Layout of the driving bindings and of the bounded vertices, which are solved
+    // to bring them back in their box
+    pub fn new<'b>(
+        bindings: impl Iterator<Item = &'b Binding>,
+        bounded: impl Iterator<Item = VertexId>,
+    ) -> Layout {
+        let bindings: Vec<&Binding> = bindings
+            .filter(|bind| bind.get_props().is_driving())
+            .collect();
+        let mut v_ids: BTreeSet<VertexId> = bounded.collect();
+        bindings.iter().for_each(|bind| bind.get_v_ids(&mut v_ids));
+        let v_ids: Vec<VertexId> = v_ids.into_iter().collect();
+        let inv_lut: BTreeMap<VertexId, usize> = v_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, v_id)| (*v_id, 2 * idx))
+            .collect();
+        let columns = bindings
+            .iter()
+            .map(|bind| {
+                bind.constraint()
+                    .v_ids()
+                    .iter()
+                    .flat_map(|v_id| [inv_lut[v_id], inv_lut[v_id] + 1])
+                    .collect()
+            })
+            .collect();
+        let mut inv_slack = BTreeMap::new();
+        bindings
+            .iter()
+            .filter(|bind| bind.bounds().is_some())
+            .for_each(|bind| {
+                let idx = 2 * v_ids.len() + inv_slack.len();
+                inv_slack.insert(bind.get_id(), idx);
+            });
+        Layout {
+            bind_ids: bindings.iter().map(|bind| bind.get_id()).collect(),
+            v_ids,
+            inv_lut,
+            columns,
+            inv_slack,
+            null_space: None,
+        }
+    }
+}
 
 pub struct Eq2DConstraints<'a> {
+    layout: Layout,
     lut: Vec<(VertexId, f64)>,
-    inv_lut: HashMap<VertexId, usize>,
+    // (lower, upper) bounds of the lut values, from the vertices bounds
+    lut_bounds: Vec<(f64, f64)>,
     bindings_pool: &'a BindingsPool,
+    // Extra equations N^T (x - x0) = 0 pinning the directions left free by the
+    // bindings, N being a basis of the jacobian null space at x0
+    completion: Option<(DMatrix<f64>, DVector<f64>)>,
+    // Slack variables of the inequality bindings, stored after the lut in the
+    // variables: (binding id, initial value, lower bound, upper bound)
+    slacks: Vec<(BindingId, f64, f64, f64)>,
+    // Unknown parameters, stored after the slacks: (name, initial value)
+    unknowns: Vec<(String, f64)>,
+    // The parameters, and the expressions of the bindings values depending on
+    // the unknowns
+    parameters: Parameters,
+    driven: BTreeMap<BindingId, Vec<Expr>>,
+    // Why the parameters couldn't be evaluated at a point the solver tried, the
+    // reason given when the solve fails
+    param_error: RefCell<Option<ParamError>>,
 }
 
 impl<'a> Eq2DConstraints<'a> {
-    pub fn new(bindings_pool: &'a mut BindingsPool, v_pool: &VerticesPool) -> Eq2DConstraints<'a> {
-        // Store the two values of each vertex from the bindings_pool
-        // linearly on a vec for the solving, along with the vertex id for the bindings
-        let mut lut = vec![];
-        let mut inv_lut = HashMap::new();
-        {
-            // Get all vertices ids that are binded, NO DUPLICATE
-            let mut v_ids = HashSet::new();
-            bindings_pool
-                .values()
-                .for_each(|bind| bind.get_v_ids(&mut v_ids));
-
-            v_ids.iter().for_each(|v_id| {
-                lut.push((*v_id, v_pool[v_id].pt.x));
-                lut.push((*v_id, v_pool[v_id].pt.y));
+    // System of all the driving bindings of the pool. The pools must belong
+    // together, e.g. to a sketch or scratch copies of it, so only the crate builds
+    // systems, see Sketch::solve
+    pub(crate) fn new(
+        bindings_pool: &'a BindingsPool,
+        v_pool: &VerticesPool,
+    ) -> Result<Eq2DConstraints<'a>, String> {
+        bindings_pool.validate(v_pool).map_err(|dangling| {
+            dangling
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })?;
+        let bounded = v_pool.values().filter(|v| v.bounds.is_some()).map(|v| v.id);
+        let layout = Layout::new(bindings_pool.values(), bounded);
+        Ok(Eq2DConstraints::with_layout(bindings_pool, v_pool, layout))
+    }
+
+    // System of a layout built on the pools, the values are read from the pools
+    pub(crate) fn with_layout(
+        bindings_pool: &'a BindingsPool,
+        v_pool: &VerticesPool,
+        layout: Layout,
+    ) -> Eq2DConstraints<'a> {
+        // The solving starts from within the bounds
+        let mut lut = Vec::with_capacity(2 * layout.v_ids.len());
+        let mut lut_bounds = Vec::with_capacity(2 * layout.v_ids.len());
+        layout.v_ids.iter().for_each(|v_id| {
+            let v = &v_pool[v_id];
+            let (x_bounds, y_bounds) = match v.bounds {
+                Some(rect) => ((rect.min_x(), rect.max_x()), (rect.min_y(), rect.max_y())),
+                None => (UNBOUNDED, UNBOUNDED),
+            };
+            lut.push((*v_id, v.pt.x.clamp(x_bounds.0, x_bounds.1)));
+            lut.push((*v_id, v.pt.y.clamp(y_bounds.0, y_bounds.1)));
+            lut_bounds.push(x_bounds);
+            lut_bounds.push(y_bounds);
+        });
+        let slacks = layout
+            .inv_slack
+            .keys()
+            .map(|id| {
+                let (lower, upper) = bindings_pool[id].bounds().unwrap();
+                (*id, 0., lower, upper)
+            })
+            .collect();
+
+        let mut cst = Eq2DConstraints {
+            layout,
+            lut,
+            lut_bounds,
+            bindings_pool,
+            completion: None,
+            slacks,
+            unknowns: vec![],
+            parameters: Parameters::new(),
+            driven: BTreeMap::new(),
+            param_error: RefCell::new(None),
+        };
+        // With null slacks the residual of an inequality is its value, the slack
+        // starts from there, clamped into its bounds
+        let x0 = cst.initial();
+        let mut rx = vec![0.; cst.eq_count()];
+        cst.residuals(&x0, &mut rx);
+        let mut idx_rx = 0;
+        let first = cst.lut.len();
+        for bind in cst.layout.bind_ids.iter().map(|id| &bindings_pool[id]) {
+            if let Some(idx) = cst.layout.inv_slack.get(&bind.get_id()) {
+                let slack = &mut cst.slacks[*idx - first];
+                slack.1 = rx[idx_rx].clamp(slack.2, slack.3);
+            }
+            idx_rx += bind.eq_count();
+        }
+        cst
+    }
+    // The layout, with the null space of the last completion, for the next solve
+    pub fn into_layout(self) -> Layout {
+        self.layout
+    }
+
+    // The driving bindings of the layout, in the order of the equations
+    fn driving(&self) -> impl Iterator<Item = &'a Binding> + '_ {
+        let pool = self.bindings_pool;
+        self.layout.bind_ids.iter().map(move |id| &pool[id])
+    }
+
+    // Solve for the unknown parameters used by the expressions driving the values
+    // of the bindings, expressions being given by binding id, one per value
+    pub fn set_unknowns(
+        &mut self,
+        parameters: &Parameters,
+        expressions: &BTreeMap<BindingId, Vec<Expr>>,
+    ) -> Result<(), String> {
+        let mut unknowns = BTreeSet::new();
+        self.driven = BTreeMap::new();
+        expressions
+            .iter()
+            .filter(|(id, _)| self.layout.bind_ids.binary_search(id).is_ok())
+            .for_each(|(id, exprs)| {
+                let used: Vec<String> = exprs
+                    .iter()
+                    .flat_map(|expr| parameters.dependencies(expr))
+                    .filter(|name| parameters[name].unknown)
+                    .collect();
+                if !used.is_empty() {
+                    self.driven.insert(*id, exprs.clone());
+                    unknowns.extend(used);
+                }
             });
+        let values = parameters
+            .values_with(&BTreeMap::new())
+            .map_err(|e| e.to_string())?;
+        self.unknowns = unknowns
+            .into_iter()
+            .map(|name| {
+                let value = values[&name];
+                (name, value)
+            })
+            .collect();
+        self.parameters = parameters.clone();
+        Ok(())
+    }
+
+    // Number of variables: the vertices coordinates, the slacks then the unknowns
+    pub fn n_vars(&self) -> usize {
+        self.lut.len() + self.slacks.len() + self.unknowns.len()
+    }
+
+    // Current values of the variables, as stored in the lut
+    pub fn initial(&self) -> Vec<f64> {
+        self.lut
+            .iter()
+            .map(|(_, value)| *value)
+            .chain(self.slacks.iter().map(|(_, value, _, _)| *value))
+            .chain(self.unknowns.iter().map(|(_, value)| *value))
+            .collect()
+    }
 
-            println!("lut: {:?}", lut);
+    // Lower and upper bounds of the variables, None when they are all unbounded
+    fn bounds(&self) -> Option<(Vec<f64>, Vec<f64>)> {
+        if self.slacks.is_empty() && self.lut_bounds.iter().all(|b| *b == UNBOUNDED) {
+            return None;
         }
+        let (lower, upper) = self
+            .lut_bounds
+            .iter()
+            .copied()
+            .chain(self.slacks.iter().map(|(_, _, lo, up)| (*lo, *up)))
+            .chain(self.unknowns.iter().map(|_| UNBOUNDED))
+            .unzip();
+        Some((lower, upper))
+    }
 
-        lut.iter()
+    fn clamp_to_bounds(&self, x: &mut DVector<f64>) {
+        if let Some((lower, upper)) = self.bounds() {
+            x.iter_mut()
+                .zip(lower.iter().zip(upper.iter()))
+                .for_each(|(xi, (lo, up))| *xi = xi.clamp(*lo, *up));
+        }
+    }
+
+    // Number of equations of the system, which can differ from the number of variables
+    pub fn eq_count(&self) -> usize {
+        let completion = self.completion.as_ref().map_or(0, |(nt, _)| nt.nrows());
+        self.driving().map(|bind| bind.eq_count()).sum::<usize>() + completion
+    }
+
+    // Parameters values, the unknowns taken from x. Err when they can't be
+    // evaluated at x, e.g. a parameter depending on the unknowns isn't finite
+    fn parameter_values(&self, x: &[f64]) -> Result<BTreeMap<String, f64>, ParamError> {
+        if self.unknowns.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        let first = self.lut.len() + self.slacks.len();
+        let overrides = self
+            .unknowns
+            .iter()
             .enumerate()
-            .step_by(2)
-            .for_each(|(idx, (v_id, _))| _ = inv_lut.insert(*v_id, idx));
+            .map(|(idx, (name, _))| (name.clone(), x[first + idx]))
+            .collect();
+        self.parameters.values_with(&overrides)
+    }
+
+    // The binding with its value evaluated from the unknowns in x, if it is driven
+    // by them
+    fn valued<'b>(&self, bind: &'b Binding, values: &BTreeMap<String, f64>) -> Cow<'b, Binding> {
+        match self.driven.get(&bind.get_id()) {
+            Some(exprs) => {
+                let mut bind = bind.clone();
+                let evaluated: Vec<f64> = exprs
+                    .iter()
+                    .map(|expr| expr.eval(values).unwrap_or(f64::NAN))
+                    .collect();
+                bind.set_values(&evaluated);
+                Cow::Owned(bind)
+            }
+            None => Cow::Borrowed(bind),
+        }
+    }
 
-        println!("inv_lut: {:?}", inv_lut);
+    // The values the idx-th binding is evaluated on, x and y of each of its vertices
+    fn constraint_values(&self, idx: usize, x: &[f64]) -> Vec<f64> {
+        self.layout.columns[idx].iter().map(|col| x[*col]).collect()
+    }
 
-        Eq2DConstraints {
-            lut,
-            inv_lut,
-            bindings_pool,
+    // Write one residual per equation in rx, rx must be eq_count long
+    pub fn residuals(&self, x: &[f64], rx: &mut [f64]) {
+        let mut idx_rx = 0;
+        // Outside of the domain of the parameters, the solvers step back from it
+        let values = match self.parameter_values(x) {
+            Ok(values) => values,
+            Err(e) => {
+                self.param_error.borrow_mut().get_or_insert(e);
+                rx.fill(f64::NAN);
+                return;
+            }
+        };
+        self.driving().enumerate().for_each(|(idx, bind)| {
+            let bind = self.valued(bind, &values);
+            let constraint = bind.constraint();
+            let n_eqs = constraint.eq_count();
+            let vals = self.constraint_values(idx, x);
+            constraint.residuals(&vals, &mut rx[idx_rx..idx_rx + n_eqs]);
+            if let Some(idx) = self.layout.inv_slack.get(&bind.get_id()) {
+                rx[idx_rx] -= x[*idx];
+            }
+            idx_rx += n_eqs;
+        });
+        if let Some((nt, x0)) = &self.completion {
+            let dx = DVector::from_column_slice(x) - x0;
+            (nt * dx).iter().for_each(|r| {
+                rx[idx_rx] = *r;
+                idx_rx += 1;
+            });
+        }
+    }
+
+    // Add the equations missing for an under-determined system: the solution is then
+    // searched in x0 + range(J^T), the solution the closest to the initial positions
+    // for linear bindings, the free DOFs staying where they are.
+    // The basis of the last solve of the layout is kept while the jacobian still
+    // vanishes on it, e.g. for the free translations of a drag, which saves its SVD
+    // and gives the same completion.
+    // Return the number of added equations.
+    fn complete_free_dofs(&mut self) -> usize {
+        self.completion = None;
+        let x0 = self.initial();
+        let n_vars = x0.len();
+        let jac = self.jacobian(&x0);
+        let nt = match self.layout.null_space.take() {
+            Some(nt) if nt.ncols() == n_vars && nt.nrows() == 0 => nt,
+            Some(nt)
+                if nt.ncols() == n_vars
+                    && (&jac * nt.transpose()).amax() <= RANK_TOLERANCE * jac.amax().max(1.) =>
+            {
+                nt
+            }
+            _ => null_space(&jac, n_vars),
+        };
+        self.layout.null_space = Some(nt.clone());
+        let added = nt.nrows();
+        if added > 0 {
+            self.completion = Some((nt, DVector::from_vec(x0)));
         }
+        added
+    }
+
+    // Jacobian of the residuals (eq_count rows, one column per variable), from
+    // the jacobians of the bindings. The columns of the unknowns are approximated
+    // by central differences
+    pub fn jacobian(&self, x: &[f64]) -> DMatrix<f64> {
+        let n_eqs = self.eq_count();
+        let mut jac = DMatrix::zeros(n_eqs, x.len());
+        let mut idx_rx = 0;
+        // See residuals
+        let values = match self.parameter_values(x) {
+            Ok(values) => values,
+            Err(e) => {
+                self.param_error.borrow_mut().get_or_insert(e);
+                return DMatrix::from_element(n_eqs, x.len(), f64::NAN);
+            }
+        };
+        self.driving().enumerate().for_each(|(idx, bind)| {
+            let bind = self.valued(bind, &values);
+            let constraint = bind.constraint();
+            let n_rows = constraint.eq_count();
+            let vals = self.constraint_values(idx, x);
+            let mut block = vec![0.; n_rows * vals.len()];
+            constraint.jacobian(&vals, &mut block);
+            // A vertex can be used twice by a binding, its derivatives add up
+            self.layout.columns[idx]
+                .iter()
+                .enumerate()
+                .for_each(|(val, col)| {
+                    for row in 0..n_rows {
+                        jac[(idx_rx + row, *col)] += block[row * vals.len() + val];
+                    }
+                });
+            if let Some(idx) = self.layout.inv_slack.get(&bind.get_id()) {
+                jac[(idx_rx, *idx)] = -1.;
+            }
+            idx_rx += n_rows;
+        });
+        if let Some((nt, _)) = &self.completion {
+            jac.rows_mut(idx_rx, nt.nrows()).copy_from(nt);
+        }
+
+        let first = self.lut.len() + self.slacks.len();
+        let mut xh = x.to_vec();
+        let mut r_plus = vec![0.; n_eqs];
+        let mut r_minus = vec![0.; n_eqs];
+        for col in first..x.len() {
+            let h = 1e-6 * x[col].abs().max(1.);
+            xh[col] = x[col] + h;
+            self.residuals(&xh, &mut r_plus);
+            xh[col] = x[col] - h;
+            self.residuals(&xh, &mut r_minus);
+            xh[col] = x[col];
+            for row in 0..n_eqs {
+                jac[(row, col)] = (r_plus[row] - r_minus[row]) / (2. * h);
+            }
+        }
+        jac
+    }
+
+    // Degrees of freedom analysis at the point x: the rank of the jacobian
+    // tells how many equations are independent
+    pub fn dof_analysis(&self, x: &[f64]) -> DofAnalysis {
+        let n_vars = x.len();
+        let n_eqs = self.eq_count();
+        let rank = if n_vars == 0 || n_eqs == 0 {
+            0
+        } else {
+            let jac = self.jacobian(x);
+            let svd = jac.svd(false, false);
+            let max_sv = svd.singular_values.max();
+            svd.rank(RANK_TOLERANCE * max_sv.max(1.))
+        };
+        DofAnalysis {
+            n_vars,
+            n_eqs,
+            rank,
+        }
+    }
+
+    // Move x onto the solution set with minimal norm Gauss-Newton steps,
+    // return false if the equations could not be satisfied
+    pub fn project(&self, x: &mut [f64], tolerance: f64, max_iter: usize) -> bool {
+        let n_eqs = self.eq_count();
+        let mut rx = vec![0.; n_eqs];
+        for _ in 0..max_iter {
+            self.residuals(x, &mut rx);
+            let r = DVector::from_column_slice(&rx);
+            if r.norm() <= tolerance {
+                return true;
+            }
+            let svd = self.jacobian(x).svd(true, true);
+            let Ok(step) = svd.solve(&r, RANK_TOLERANCE) else {
+                return false;
+            };
+            x.iter_mut().zip(step.iter()).for_each(|(xi, si)| *xi -= si);
+        }
+        self.residuals(x, &mut rx);
+        DVector::from_column_slice(&rx).norm() <= tolerance
+    }
+
+    // Positions of the binded vertices for the values x of the variables
+    pub fn solution(&self, x: &[f64]) -> Vec<(VertexId, Point)> {
+        self.layout
+            .inv_lut
+            .iter()
+            .map(|(v_id, idx)| (*v_id, Point::new(x[*idx], x[*idx + 1])))
+            .collect()
+    }
+    // Values of the unknown parameters at x
+    pub fn solved_unknowns(&self, x: &[f64]) -> Vec<(String, f64)> {
+        let first = self.lut.len() + self.slacks.len();
+        self.unknowns
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, _))| (name.clone(), x[first + idx]))
+            .collect()
     }
 
-    pub fn solve(&mut self, v_pool: &mut VerticesPool) -> Result<(), String> {
-        let mut init = vec![];
-        for (_, value) in self.lut.iter() {
-            init.push(*value);
+    // Run the solver from the lut values, return the values of the variables
+    // and the norm of the residuals at these values.
+    // Soft bindings and non-square systems with bounded variables go to
+    // solve_constrained. Otherwise free DOFs are first pinned (see complete_free_dofs),
+    // then square systems go to gomez, within the bounds of the variables, and the
+    // others are solved in the least squares sense.
+    pub fn solve(&mut self, opts: &SolveOptions) -> Result<(Vec<f64>, f64), String> {
+        if self.lut.is_empty() {
+            return Ok((vec![], 0.));
+        }
+        // Bounded vertices without bindings, the initial values are already
+        // clamped into the bounds
+        if self.eq_count() == 0 {
+            return Ok((self.initial(), 0.));
+        }
+        self.param_error.replace(None);
+        let result = self.solve_system(opts);
+        // A solve stopped by parameters that can't be evaluated tells which
+        match (result, self.param_error.take()) {
+            (Ok((_, norm)), Some(e)) if norm > opts.tolerance => Err(e.to_string()),
+            (Err(_), Some(e)) => Err(e.to_string()),
+            (result, _) => result,
+        }
+    }
+
+    // See solve
+    fn solve_system(&mut self, opts: &SolveOptions) -> Result<(Vec<f64>, f64), String> {
+        let soft = self
+            .driving()
+            .any(|bind| bind.get_props().strength.weight().is_some());
+        if soft || (self.bounds().is_some() && self.eq_count() != self.n_vars()) {
+            return self.solve_constrained(opts);
+        }
+        let kept = self.layout.null_space.is_some();
+        match self.solve_completed(opts) {
+            Ok((vals, norm)) if norm <= opts.tolerance || !kept => Ok((vals, norm)),
+            // The kept basis may not span the null space anymore, e.g. after a
+            // singular position, try again with a new one
+            _ if kept => {
+                self.layout.null_space = None;
+                self.solve_completed(opts)
+            }
+            result => result,
+        }
+    }
+
+    // Solve with the free DOFs pinned, see complete_free_dofs
+    fn solve_completed(&mut self, opts: &SolveOptions) -> Result<(Vec<f64>, f64), String> {
+        let added = self.complete_free_dofs();
+        if opts.verbose && added > 0 {
+            eprintln!("under-determined: {added} equations added on the free DOFs");
+        }
+        if self.eq_count() != self.n_vars() {
+            return self.solve_least_squares(opts);
+        }
+        let init = self.initial();
+        if opts.verbose {
+            eprintln!("init: {:?}", init);
         }
-        println!("init: {:?}", init);
         let mut solver = SolverDriver::builder(self).with_initial(init).build();
-        let tolerance = 1e-6;
         let (vals, norm) = solver
             .find(|state| {
-                println!(
-                    "iter = {}\t||r(x)|| = {}\tx = {:?}",
-                    state.iter(),
-                    state.norm(),
-                    state.x()
-                );
-                // println!("iter = {}", state.iter(),);
-                state.norm() <= tolerance || state.iter() >= 100
+                if opts.verbose {
+                    eprintln!(
+                        "iter = {}\t||r(x)|| = {}\tx = {:?}",
+                        state.iter(),
+                        state.norm(),
+                        state.x()
+                    );
+                }
+                state.norm() <= opts.tolerance || state.iter() >= opts.max_iter
             })
             .map_err(|error| format!("{error}"))?;
 
-        println!("vals: {:?} ", vals);
+        if opts.verbose {
+            eprintln!("vals: {:?} ", vals);
+        }
+        Ok((vals.to_vec(), norm))
+    }
 
-        self.inv_lut.iter().for_each(|(v_id, idx)| {
-            let v = v_pool.get_mut(v_id).unwrap();
-            v.pt.x = vals[*idx];
-            v.pt.y = vals[*idx + 1];
+    // Weight of each equation, in the order of the residuals, None when required
+    fn row_weights(&self) -> Vec<Option<f64>> {
+        let mut weights = vec![];
+        self.driving().for_each(|bind| {
+            let weight = bind.get_props().strength.weight();
+            (0..bind.eq_count()).for_each(|_| weights.push(weight));
         });
+        weights.resize(self.eq_count(), None);
+        weights
+    }
 
-        if norm <= tolerance {
-            Ok(())
-        } else {
-            Err("did not converge".to_string())
+    // Solve with soft bindings or bounded variables: the required equations h(x) = 0
+    // are satisfied exactly while the weighted squared violation of the soft ones,
+    // 0.5 * ||W^1/2 s(x)||^2, is minimised. A tiny weight STAY_WEIGHT on the moves
+    // of the vertices keeps the free DOFs where they are. Each iteration solves the
+    // damped KKT system
+    //   [Js^T W Js + lambda I   Jh^T   E^T] [dx]   [-Js^T W s]
+    //   [Jh                     0      0  ] [mu] = [-h       ]
+    //   [E                      0      0  ]        [0        ]
+    // E stopping the variables whose step would cross a bound on it (active set),
+    // and the step is accepted when it decreases the merit
+    // 0.5 * ||W^1/2 s||^2 + nu * ||h||_1. The returned norm is the one of h.
+    fn solve_constrained(&self, opts: &SolveOptions) -> Result<(Vec<f64>, f64), String> {
+        let n_vars = self.n_vars();
+        let n_coords = self.lut.len();
+        let weights = self.row_weights();
+        let req: Vec<usize> = (0..weights.len())
+            .filter(|i| weights[*i].is_none())
+            .collect();
+        let soft: Vec<(usize, f64)> = weights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, w)| w.map(|w| (i, w.sqrt())))
+            .collect();
+        let (lower, upper) = self
+            .bounds()
+            .unwrap_or_else(|| (vec![f64::NEG_INFINITY; n_vars], vec![f64::INFINITY; n_vars]));
+        let x0 = DVector::from_vec(self.initial());
+        let stay = STAY_WEIGHT.sqrt();
+        // Required residuals, then the weighted soft residuals and the moves
+        let split = |x: &DVector<f64>, rx: &[f64]| {
+            let h = DVector::from_iterator(req.len(), req.iter().map(|i| rx[*i]));
+            let sw = DVector::from_iterator(
+                soft.len() + n_coords,
+                soft.iter()
+                    .map(|(i, w)| w * rx[*i])
+                    .chain((0..n_coords).map(|i| stay * (x[i] - x0[i]))),
+            );
+            (h, sw)
+        };
+        let merit = |h: &DVector<f64>, sw: &DVector<f64>, nu: f64| {
+            0.5 * sw.norm_squared() + nu * h.lp_norm(1)
+        };
+
+        let mut x = x0.clone();
+        let mut rx = vec![0.; weights.len()];
+        self.residuals(x.as_slice(), &mut rx);
+        let (mut h, mut sw) = split(&x, &rx);
+        let mut nu: f64 = 1.;
+        let mut lambda = 1e-3;
+        let mut iter = 0;
+        while iter < opts.max_iter {
+            let jac = self.jacobian(x.as_slice());
+            let jh = DMatrix::from_fn(req.len(), n_vars, |r, c| jac[(req[r], c)]);
+            let jw = DMatrix::from_fn(soft.len() + n_coords, n_vars, |r, c| {
+                if r < soft.len() {
+                    soft[r].1 * jac[(soft[r].0, c)]
+                } else if r - soft.len() == c {
+                    stay
+                } else {
+                    0.
+                }
+            });
+            let hess = jw.transpose() * &jw;
+            let grad = jw.transpose() * &sw;
+            let scale = hess.diagonal().max().max(1.);
+            // Without required equations, e.g. soft bindings only, there is
+            // nothing to correct
+            let jh_svd = (!req.is_empty()).then(|| jh.clone().svd(true, true));
+
+            let mut accepted = None;
+            while lambda <= LM_MAX_LAMBDA {
+                let damped = &hess + DMatrix::identity(n_vars, n_vars) * (lambda * scale);
+                // Grow the active set until the step stays within the bounds, a
+                // variable crossing a bound is stopped on it
+                let mut fixed: Vec<(usize, f64)> = vec![];
+                let step = loop {
+                    let Some((dx, mu)) = kkt_step(&damped, &grad, &jh, &h, &fixed) else {
+                        break None;
+                    };
+                    let crossing: Vec<(usize, f64)> = (0..n_vars)
+                        .filter(|i| fixed.iter().all(|(var, _)| var != i))
+                        .filter_map(|i| {
+                            let target = x[i] + dx[i];
+                            if target < lower[i] {
+                                Some((i, lower[i] - x[i]))
+                            } else if target > upper[i] {
+                                Some((i, upper[i] - x[i]))
+                            } else {
+                                None
+                            }
+                        })
+                        .collect();
+                    if crossing.is_empty() {
+                        break Some((dx, mu));
+                    }
+                    fixed.extend(crossing);
+                };
+                let Some((dx, mu)) = step else {
+                    lambda *= 10.;
+                    continue;
+                };
+                // The merit weight must dominate the multipliers of the required equations
+                nu = nu.max(2. * mu.amax() + 1.);
+
+                // Backtrack along the step, the linearised required equations
+                // don't limit its length
+                let current = merit(&h, &sw, nu);
+                let mut alpha = 1.;
+                let mut correction = jh_svd.is_some();
+                while alpha >= MIN_STEP_FRACTION {
+                    let mut x_new = &x + &dx * alpha;
+                    if correction {
+                        // Second order correction: pull the full step back onto
+                        // the required equations, the minimal norm way
+                        let mut rx_new = vec![0.; rx.len()];
+                        self.residuals(x_new.as_slice(), &mut rx_new);
+                        let (h_new, _) = split(&x_new, &rx_new);
+                        let svd = jh_svd.as_ref().unwrap();
+                        if let Ok(dc) = svd.solve(&h_new, RANK_TOLERANCE) {
+                            x_new -= dc;
+                        }
+                    }
+                    self.clamp_to_bounds(&mut x_new);
+                    let mut rx_new = vec![0.; rx.len()];
+                    self.residuals(x_new.as_slice(), &mut rx_new);
+                    let (h_new, sw_new) = split(&x_new, &rx_new);
+                    if merit(&h_new, &sw_new, nu) < current {
+                        accepted = Some((&x_new - &x).norm());
+                        x = x_new;
+                        (h, sw) = (h_new, sw_new);
+                        break;
+                    }
+                    if correction {
+                        correction = false;
+                    } else {
+                        alpha /= 2.;
+                    }
+                }
+                if accepted.is_some() {
+                    lambda = (lambda / 10.).max(1e-12);
+                    break;
+                }
+                lambda *= 10.;
+            }
+            iter += 1;
+            if opts.verbose {
+                eprintln!(
+                    "iter = {}\t||h(x)|| = {}\t||s(x)|| = {}\tx = {:?}",
+                    iter,
+                    h.norm(),
+                    sw.norm(),
+                    x.as_slice()
+                );
+            }
+            match accepted {
+                // Optimum: nothing improves the merit anymore
+                None => break,
+                Some(step)
+                    if step <= STEP_TOLERANCE * (1. + x.norm()) && h.norm() <= opts.tolerance =>
+                {
+                    break
+                }
+                _ => (),
+            }
+        }
+        let norm = h.norm();
+        if norm > opts.tolerance && lambda > LM_MAX_LAMBDA {
+            return Err(format!(
+                "inconsistent required bindings, residual {norm:e} can't reach the tolerance"
+            ));
+        }
+        if opts.verbose {
+            eprintln!("vals: {:?} ", x.as_slice());
+        }
+        Ok((x.as_slice().to_vec(), norm))
+    }
+
+    // Levenberg-Marquardt on the sum of the squared residuals, for systems where the
+    // number of equations differs from the number of variables. An over-determined
+    // but consistent system still reaches a zero residual, an inconsistent one gets
+    // stuck on a non zero minimum, which is reported as an error.
+    fn solve_least_squares(&self, opts: &SolveOptions) -> Result<(Vec<f64>, f64), String> {
+        let n_vars = self.n_vars();
+        let mut x = DVector::from_vec(self.initial());
+        let mut rx = vec![0.; self.eq_count()];
+        self.residuals(x.as_slice(), &mut rx);
+        let mut norm = DVector::from_column_slice(&rx).norm();
+        let mut lambda = 1e-3;
+        let mut iter = 0;
+        while norm > opts.tolerance && iter < opts.max_iter {
+            let jac = self.jacobian(x.as_slice());
+            let jtj = jac.transpose() * &jac;
+            let grad = jac.transpose() * DVector::from_column_slice(&rx);
+            // Damping scaled on the jacobian so lambda doesn't depend on the units
+            let scale = jtj.diagonal().max().max(1e-12);
+            // A vanishing gradient with a non zero residual is a minimum that is not
+            // a solution, nothing will improve it
+            let stationary = grad.norm() <= RANK_TOLERANCE * jac.norm() * norm;
+            let mut improved = false;
+            while !stationary && lambda <= LM_MAX_LAMBDA {
+                let damped = &jtj + DMatrix::identity(n_vars, n_vars) * (lambda * scale);
+                let Some(step) = damped.cholesky().map(|chol| chol.solve(&-&grad)) else {
+                    lambda *= 10.;
+                    continue;
+                };
+                let mut x_new = &x + step;
+                self.clamp_to_bounds(&mut x_new);
+                let mut rx_new = vec![0.; rx.len()];
+                self.residuals(x_new.as_slice(), &mut rx_new);
+                let norm_new = DVector::from_column_slice(&rx_new).norm();
+                if norm_new < norm {
+                    x = x_new;
+                    rx = rx_new;
+                    norm = norm_new;
+                    lambda = (lambda / 10.).max(1e-12);
+                    improved = true;
+                    break;
+                }
+                lambda *= 10.;
+            }
+            iter += 1;
+            if opts.verbose {
+                eprintln!(
+                    "iter = {}\t||r(x)|| = {}\tx = {:?}",
+                    iter,
+                    norm,
+                    x.as_slice()
+                );
+            }
+            if !improved {
+                return Err(format!(
+                    "inconsistent bindings, least squares residual {norm:e} can't reach the tolerance"
+                ));
+            }
+        }
+        if opts.verbose {
+            eprintln!("vals: {:?} ", x.as_slice());
+        }
+        Ok((x.as_slice().to_vec(), norm))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SolveOptions {
+    // Norm of the residuals under which the system is solved
+    pub tolerance: f64,
+    pub max_iter: usize,
+    // Print the solver iterations, on the standard error
+    pub verbose: bool,
+    // Keep the chirality of the triangles and the relative direction of the
+    // parallel and perpendicular segments, see branches::orientations. Applied by
+    // Sketch::solve which guards the bindings with inequalities
+    pub preserve_orientation: bool,
+}
+impl Default for SolveOptions {
+    fn default() -> Self {
+        SolveOptions {
+            tolerance: 1e-6,
+            max_iter: 100,
+            verbose: false,
+            preserve_orientation: false,
         }
     }
 }
@@ -85,7 +844,10 @@ impl<'a> Eq2DConstraints<'a> {
 impl<'a> Problem for Eq2DConstraints<'a> {
     type Field = f64;
     fn domain(&self) -> Domain<Self::Field> {
-        Domain::unconstrained(self.lut.len())
+        match self.bounds() {
+            Some((lower, upper)) => Domain::rect(lower, upper),
+            None => Domain::unconstrained(self.n_vars()),
+        }
     }
 }
 
@@ -98,64 +860,95 @@ impl<'a> System for Eq2DConstraints<'a> {
         Sx: gomez::nalgebra::storage::Storage<Self::Field, Dyn> + IsContiguous,
         Srx: gomez::nalgebra::storage::StorageMut<Self::Field, Dyn>,
     {
-        let mut idx_rx = 0;
-        self.bindings_pool.iter().for_each(|(_, bind)| match bind {
-            Binding::Fixed(b) => {
-                let bind = b.bind(&[x[self.inv_lut[&b.v_id]], x[self.inv_lut[&b.v_id] + 1]]);
-                rx[idx_rx] = bind[0];
-                idx_rx += 1;
-                rx[idx_rx] = bind[1];
-                idx_rx += 1;
-            }
-            Binding::FixedX(b) => {
-                rx[idx_rx] = b.bind(&[x[self.inv_lut[&b.v_id]], x[self.inv_lut[&b.v_id] + 1]]);
-                idx_rx += 1;
-            }
-            Binding::FixedY(b) => {
-                rx[idx_rx] = b.bind(&[x[self.inv_lut[&b.v_id]], x[self.inv_lut[&b.v_id] + 1]]);
-                idx_rx += 1;
-            }
-            Binding::Vertical(b) => {
-                rx[idx_rx] = b.bind(&[
-                    x[self.inv_lut[&b.va_id]],
-                    x[self.inv_lut[&b.va_id] + 1],
-                    x[self.inv_lut[&b.vb_id]],
-                    x[self.inv_lut[&b.vb_id] + 1],
-                ]);
-                idx_rx += 1;
-            }
-            Binding::Horizontal(b) => {
-                rx[idx_rx] = b.bind(&[
-                    x[self.inv_lut[&b.va_id]],
-                    x[self.inv_lut[&b.va_id] + 1],
-                    x[self.inv_lut[&b.vb_id]],
-                    x[self.inv_lut[&b.vb_id] + 1],
-                ]);
-                idx_rx += 1;
-            }
-            Binding::Parallel(b) => {
-                rx[idx_rx] = b.bind(&[
-                    x[self.inv_lut[&b.l1va_id]],
-                    x[self.inv_lut[&b.l1va_id] + 1],
-                    x[self.inv_lut[&b.l1vb_id]],
-                    x[self.inv_lut[&b.l1vb_id] + 1],
-                    x[self.inv_lut[&b.l2va_id]],
-                    x[self.inv_lut[&b.l2va_id] + 1],
-                    x[self.inv_lut[&b.l2vb_id]],
-                    x[self.inv_lut[&b.l2vb_id] + 1],
-                ]);
-                idx_rx += 1;
-            }
-            Binding::Distance(b) => {
-                rx[idx_rx] = b.bind(&[
-                    x[self.inv_lut[&b.va_id]],
-                    x[self.inv_lut[&b.va_id] + 1],
-                    x[self.inv_lut[&b.vb_id]],
-                    x[self.inv_lut[&b.vb_id] + 1],
-                ]);
-                idx_rx += 1;
-            }
-            Binding::Error(_) => (),
-        });
+        let mut res = vec![0.; self.eq_count()];
+        self.residuals(x.as_slice(), &mut res);
+        res.iter().enumerate().for_each(|(idx, r)| rx[idx] = *r);
+    }
+}
+
+const UNBOUNDED: (f64, f64) = (f64::NEG_INFINITY, f64::INFINITY);
+
+// Damping above which Levenberg-Marquardt gives up improving the residual
+const LM_MAX_LAMBDA: f64 = 1e12;
+
+// Relative step length under which the constrained solve stops
+const STEP_TOLERANCE: f64 = 1e-10;
+
+// Smallest fraction of the step tried by the backtracking of the constrained solve
+const MIN_STEP_FRACTION: f64 = 1e-4;
+
+// Weight of the squared moves of the vertices in the constrained solve, well under
+// Strength::Weak so it only decides between otherwise equivalent solutions
+const STAY_WEIGHT: f64 = 1e-8;
+
+// Solve the KKT system of solve_constrained for the step dx and the multipliers mu
+// of the required equations, fixed gives the imposed steps of the active variables
+fn kkt_step(
+    damped: &DMatrix<f64>,
+    grad: &DVector<f64>,
+    jh: &DMatrix<f64>,
+    h: &DVector<f64>,
+    fixed: &[(usize, f64)],
+) -> Option<(DVector<f64>, DVector<f64>)> {
+    let n_vars = damped.nrows();
+    let n_req = jh.nrows();
+    let dim = n_vars + n_req + fixed.len();
+    let mut kkt = DMatrix::zeros(dim, dim);
+    kkt.view_mut((0, 0), (n_vars, n_vars)).copy_from(damped);
+    kkt.view_mut((0, n_vars), (n_vars, n_req))
+        .copy_from(&jh.transpose());
+    kkt.view_mut((n_vars, 0), (n_req, n_vars)).copy_from(jh);
+    let mut rhs = DVector::zeros(dim);
+    rhs.rows_mut(0, n_vars).copy_from(&-grad);
+    rhs.rows_mut(n_vars, n_req).copy_from(&-h);
+    fixed.iter().enumerate().for_each(|(row, (var, step))| {
+        kkt[(n_vars + n_req + row, *var)] = 1.;
+        kkt[(*var, n_vars + n_req + row)] = 1.;
+        rhs[n_vars + n_req + row] = *step;
+    });
+    let sol = kkt.svd(true, true).solve(&rhs, RANK_TOLERANCE).ok()?;
+    Some((
+        sol.rows(0, n_vars).into_owned(),
+        sol.rows(n_vars, n_req).into_owned(),
+    ))
+}
+
+// Relative threshold on the singular values of the jacobian under which
+// an equation is considered dependent on the others
+const RANK_TOLERANCE: f64 = 1e-9;
+
+// Basis of the null space of the jacobian, as the rows of the returned matrix
+fn null_space(jac: &DMatrix<f64>, n_vars: usize) -> DMatrix<f64> {
+    // Padding with zero rows up to a square matrix gives the full V in the SVD
+    let mut padded = DMatrix::zeros(jac.nrows().max(n_vars), n_vars);
+    padded.rows_mut(0, jac.nrows()).copy_from(jac);
+    let svd = padded.svd(false, true);
+    let threshold = RANK_TOLERANCE * svd.singular_values.max().max(1.);
+    let v_t = svd.v_t.unwrap();
+    let null_rows: Vec<usize> = (0..n_vars)
+        .filter(|idx| svd.singular_values[*idx] <= threshold)
+        .collect();
+    DMatrix::from_fn(null_rows.len(), n_vars, |row, col| {
+        v_t[(null_rows[row], col)]
+    })
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DofAnalysis {
+    pub n_vars: usize,
+    pub n_eqs: usize,
+    pub rank: usize,
+}
+impl DofAnalysis {
+    // Remaining degrees of freedom
+    pub fn dof(&self) -> usize {
+        self.n_vars - self.rank
+    }
+    // Number of equations that don't constrain anything new
+    pub fn redundant(&self) -> usize {
+        self.n_eqs - self.rank
+    }
+    pub fn is_over_constrained(&self) -> bool {
+        self.redundant() > 0
     }
 }
@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::f64::consts::PI;
+
+use kurbo::{Point, Vec2};
+
+use crate::bindings::{Eq2DConstraints, SolveOptions};
+use crate::math::*;
+
+// Relative size under which a triangle or a pair of segments has no orientation
+const DEGENERATE: f64 = 1e-6;
+// Enumerating the branches solves 2^n systems for n orientations, up to 1024
+// solves: past that enumerate_branches gives up with an error
+const MAX_ENUMERATED: usize = 10;
+
+// A sign of the configuration that the nonlinear bindings let flip: a mirrored
+// triangle or a reversed segment are solutions too
+#[derive(Copy, Clone, Debug)]
+pub enum Orientation {
+    // Side of the line (la, lb) where v is, the chirality of the triangle
+    Triangle {
+        v_id: VertexId,
+        la_id: VertexId,
+        lb_id: VertexId,
+    },
+    // Sign of the dot product of the segments, whether parallel segments point
+    // the same way
+    Direction {
+        seg1: (VertexId, VertexId),
+        seg2: (VertexId, VertexId),
+    },
+    // Sign of the cross product of the segments, the way perpendicular segments turn
+    Rotation {
+        seg1: (VertexId, VertexId),
+        seg2: (VertexId, VertexId),
+    },
+}
+
+impl Orientation {
+    // 1 or -1, None when degenerate
+    pub fn sign(&self, v_pool: &VerticesPool) -> Option<f64> {
+        let vec = |(va, vb): (VertexId, VertexId)| v_pool[&vb].pt - v_pool[&va].pt;
+        let (value, scale) = match *self {
+            Orientation::Triangle { v_id, la_id, lb_id } => {
+                let (d1, d2) = (vec((la_id, lb_id)), vec((la_id, v_id)));
+                (d1.cross(d2), d1.hypot2() + d2.hypot2())
+            }
+            Orientation::Direction { seg1, seg2 } => {
+                let (d1, d2) = (vec(seg1), vec(seg2));
+                (d1.dot(d2), d1.hypot2() + d2.hypot2())
+            }
+            Orientation::Rotation { seg1, seg2 } => {
+                let (d1, d2) = (vec(seg1), vec(seg2));
+                (d1.cross(d2), d1.hypot2() + d2.hypot2())
+            }
+        };
+        if value.abs() <= DEGENERATE * scale {
+            None
+        } else {
+            Some(value.signum())
+        }
+    }
+
+    // Move a vertex so the sign is reversed, the start of the solve of the other
+    // branch: crossing the degenerate configuration is where the solver stalls
+    fn flip(&self, v_pool: &mut VerticesPool) {
+        let pt = |v_pool: &VerticesPool, v_id: VertexId| v_pool[&v_id].pt;
+        // Reflection of p across the line through o along d
+        let reflect = |p: Point, o: Point, d: Vec2| {
+            let n = Vec2::new(-d.y, d.x);
+            p - 2. * n.dot(p - o) / n.hypot2() * n
+        };
+        let (v_id, moved) = match *self {
+            Orientation::Triangle { v_id, la_id, lb_id } => {
+                let (la, lb) = (pt(v_pool, la_id), pt(v_pool, lb_id));
+                (v_id, reflect(pt(v_pool, v_id), la, lb - la))
+            }
+            Orientation::Direction { seg2, .. } => {
+                let (va, vb) = (pt(v_pool, seg2.0), pt(v_pool, seg2.1));
+                (seg2.1, va - (vb - va))
+            }
+            Orientation::Rotation { seg1, seg2 } => {
+                let d1 = pt(v_pool, seg1.1) - pt(v_pool, seg1.0);
+                (seg2.1, reflect(pt(v_pool, seg2.1), pt(v_pool, seg2.0), d1))
+            }
+        };
+        v_pool.set_pt(&v_id, moved);
+    }
+
+    // Add the inequality binding keeping the sign. A binding already on these
+    // vertices (a duplicate) is left in charge
+    fn guard(&self, pool: &mut BindingsPool, v_pool: &VerticesPool, sign: f64) {
+        let seg = |(va, vb): (VertexId, VertexId)| (&v_pool[&va], &v_pool[&vb]);
+        match *self {
+            Orientation::Triangle { v_id, la_id, lb_id } => {
+                if let Ok(b) = pool.add_bind_half_plane(&v_pool[&v_id], seg((la_id, lb_id))) {
+                    if let Some(Binding::HalfPlane(b)) = pool.get_mut(&b.id) {
+                        b.side = sign;
+                    }
+                }
+            }
+            Orientation::Direction { seg1, seg2 } => {
+                let seg2 = if sign < 0. { (seg2.1, seg2.0) } else { seg2 };
+                _ = pool.add_bind_angle_range(seg(seg1), seg(seg2), -PI / 2., PI / 2.);
+            }
+            Orientation::Rotation { seg1, seg2 } => {
+                let (min_angle, max_angle) = if sign < 0. { (-PI, 0.) } else { (0., PI) };
+                _ = pool.add_bind_angle_range(seg(seg1), seg(seg2), min_angle, max_angle);
+            }
+        }
+    }
+}
+
+// A solution of the bindings, signs are the ones of the orientations in the order
+// given by orientations
+#[derive(Clone, Debug)]
+pub struct Branch {
+    pub signs: Vec<f64>,
+    pub positions: Vec<(VertexId, Point)>,
+}
+
+// The orientations of the sketch that aren't degenerate: the triangles formed by
+// the segments of the line shapes and of the bindings, and the parallel and
+// perpendicular bindings
+pub fn orientations(
+    v_pool: &VerticesPool,
+    shapes_pool: &ShapesPool,
+    bindings_pool: &BindingsPool,
+) -> Vec<Orientation> {
+    let mut segments = BTreeSet::new();
+    let mut add_segment = |va: VertexId, vb: VertexId| {
+        if va != vb {
+            segments.insert((va.min(vb), va.max(vb)));
+        }
+    };
+    shapes_pool.values().for_each(|shape| match shape {
+        ShapeType::STLine(line) => add_segment(line.va_id, line.vb_id),
+    });
+    let mut orientations = vec![];
+    bindings_pool.driving().for_each(|bind| match bind {
+        Binding::Vertical(b) => add_segment(b.va_id, b.vb_id),
+        Binding::Horizontal(b) => add_segment(b.va_id, b.vb_id),
+        Binding::Distance(b) => add_segment(b.va_id, b.vb_id),
+        Binding::DistanceRange(b) => add_segment(b.va_id, b.vb_id),
+        Binding::Parallel(b) => {
+            add_segment(b.l1va_id, b.l1vb_id);
+            add_segment(b.l2va_id, b.l2vb_id);
+            orientations.push(Orientation::Direction {
+                seg1: (b.l1va_id, b.l1vb_id),
+                seg2: (b.l2va_id, b.l2vb_id),
+            });
+        }
+        Binding::Perpendicular(b) => {
+            add_segment(b.l1va_id, b.l1vb_id);
+            add_segment(b.l2va_id, b.l2vb_id);
+            orientations.push(Orientation::Rotation {
+                seg1: (b.l1va_id, b.l1vb_id),
+                seg2: (b.l2va_id, b.l2vb_id),
+            });
+        }
+        Binding::EqualLength(b) => {
+            add_segment(b.l1va_id, b.l1vb_id);
+            add_segment(b.l2va_id, b.l2vb_id);
+        }
+        Binding::AngleRange(b) => {
+            add_segment(b.l1va_id, b.l1vb_id);
+            add_segment(b.l2va_id, b.l2vb_id);
+        }
+        _ => (),
+    });
+
+    let mut neighbours: BTreeMap<VertexId, BTreeSet<VertexId>> = BTreeMap::new();
+    segments
+        .iter()
+        .filter(|(va, vb)| v_pool.contains_key(va) && v_pool.contains_key(vb))
+        .for_each(|(va, vb)| {
+            neighbours.entry(*va).or_default().insert(*vb);
+            neighbours.entry(*vb).or_default().insert(*va);
+        });
+    let mut triangles = vec![];
+    neighbours.iter().for_each(|(va, na)| {
+        na.range(*va..).for_each(|vb| {
+            neighbours[vb]
+                .range(*vb..)
+                .filter(|vc| na.contains(vc))
+                .for_each(|vc| {
+                    triangles.push(Orientation::Triangle {
+                        v_id: *vc,
+                        la_id: *va,
+                        lb_id: *vb,
+                    })
+                });
+        });
+    });
+    triangles.append(&mut orientations);
+    triangles.retain(|orientation| {
+        let ids = match *orientation {
+            Orientation::Triangle { v_id, la_id, lb_id } => vec![v_id, la_id, lb_id],
+            Orientation::Direction { seg1, seg2 } | Orientation::Rotation { seg1, seg2 } => {
+                vec![seg1.0, seg1.1, seg2.0, seg2.1]
+            }
+        };
+        ids.iter().all(|v_id| v_pool.contains_key(v_id)) && orientation.sign(v_pool).is_some()
+    });
+    triangles
+}
+
+// Copy of bindings_pool with a guard per orientation keeping the given sign
+pub fn guarded(
+    bindings_pool: &BindingsPool,
+    v_pool: &VerticesPool,
+    orientations: &[(Orientation, f64)],
+) -> BindingsPool {
+    let mut pool = bindings_pool.clone();
+    orientations
+        .iter()
+        .for_each(|(orientation, sign)| orientation.guard(&mut pool, v_pool, *sign));
+    pool
+}
+
+// Solve the bindings for every combination of signs of the orientations, starting
+// from the current positions with the flipped orientations mirrored. The branches
+// that converge with all their orientations well defined are returned, the current
+// one first when it is found. Err past MAX_ENUMERATED orientations
+pub fn enumerate_branches(
+    v_pool: &VerticesPool,
+    shapes_pool: &ShapesPool,
+    bindings_pool: &BindingsPool,
+    opts: &SolveOptions,
+) -> Result<Vec<Branch>, String> {
+    let orientations = orientations(v_pool, shapes_pool, bindings_pool);
+    if orientations.len() > MAX_ENUMERATED {
+        return Err(format!(
+            "{} orientations, branches can be enumerated up to {MAX_ENUMERATED}",
+            orientations.len()
+        ));
+    }
+    let initial: Vec<f64> = orientations
+        .iter()
+        .map(|orientation| orientation.sign(v_pool).unwrap())
+        .collect();
+
+    let mut branches = vec![];
+    for flips in 0..1usize << orientations.len() {
+        let signs: Vec<f64> = initial
+            .iter()
+            .enumerate()
+            .map(|(idx, sign)| if flips >> idx & 1 == 1 { -sign } else { *sign })
+            .collect();
+        let guards: Vec<(Orientation, f64)> = orientations
+            .iter()
+            .copied()
+            .zip(signs.iter().copied())
+            .collect();
+        let mut start = v_pool.clone();
+        orientations
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| flips >> idx & 1 == 1)
+            .for_each(|(_, orientation)| orientation.flip(&mut start));
+        let pool = guarded(bindings_pool, &start, &guards);
+        let mut cst = Eq2DConstraints::new(&pool, &start)?;
+        let positions = match cst.solve(opts) {
+            Ok((x, norm)) if norm <= opts.tolerance => cst.solution(&x),
+            _ => continue,
+        };
+        // A guard at its bound is the limit of the branch, a degenerate configuration
+        let mut scratch = v_pool.clone();
+        positions.iter().for_each(|(v_id, pt)| {
+            scratch.set_pt(v_id, *pt);
+        });
+        if guards
+            .iter()
+            .all(|(orientation, sign)| orientation.sign(&scratch) == Some(*sign))
+        {
+            branches.push(Branch { signs, positions });
+        }
+    }
+    Ok(branches)
+}
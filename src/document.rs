@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::math::*;
+use crate::sketch::Sketch;
+
+// Version of the documents written by to_json. Documents of older versions are
+// migrated when read, one version at a time, see migrate
+pub const VERSION: u64 = 1;
+
+// A sketch as saved: the pools with their ids, the parameters and the
+// expressions by their source. The solver state is not saved
+#[derive(Serialize, Deserialize)]
+struct Document {
+    version: u64,
+    vertices: VerticesPool,
+    shapes: ShapesPool,
+    bindings: BindingsPool,
+    // In the order of the dependencies, so they can be defined again one by one
+    parameters: Vec<ParameterEntry>,
+    expressions: BTreeMap<BindingId, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ParameterEntry {
+    name: String,
+    source: String,
+    // The value of an unknown is its last solution
+    value: f64,
+    unknown: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DocumentError {
+    Json(String),
+    MissingVersion,
+    UnsupportedVersion(u64),
+    // Custom bindings are only known to the program that added them
+    CustomBinding(BindingId),
+    Invalid(String),
+}
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::Json(msg) => write!(f, "invalid json: {msg}"),
+            DocumentError::MissingVersion => write!(f, "the document has no version"),
+            DocumentError::UnsupportedVersion(version) => write!(
+                f,
+                "document version {version} is not supported, the latest is {VERSION}"
+            ),
+            DocumentError::CustomBinding(id) => {
+                write!(f, "custom binding {} can't be saved", **id)
+            }
+            DocumentError::Invalid(msg) => write!(f, "invalid document: {msg}"),
+        }
+    }
+}
+
+fn json_error(err: serde_json::Error) -> DocumentError {
+    DocumentError::Json(err.to_string())
+}
+
+pub fn to_json(sketch: &Sketch) -> Result<String, DocumentError> {
+    if let Some(bind) = sketch
+        .bindings()
+        .values()
+        .find(|bind| matches!(bind, Binding::Custom(_)))
+    {
+        return Err(DocumentError::CustomBinding(bind.get_id()));
+    }
+    let parameters = sketch.parameters();
+    let order = parameters
+        .order()
+        .map_err(|e| DocumentError::Invalid(e.to_string()))?;
+    let document = Document {
+        version: VERSION,
+        vertices: sketch.vertices().clone(),
+        shapes: sketch.shapes().clone(),
+        bindings: sketch.bindings().clone(),
+        parameters: order
+            .into_iter()
+            .map(|name| {
+                let param = &parameters[&name];
+                ParameterEntry {
+                    source: param.source.clone(),
+                    value: param.value,
+                    unknown: param.unknown,
+                    name,
+                }
+            })
+            .collect(),
+        expressions: sketch
+            .binding_exprs()
+            .map(|(id, source)| (*id, source.to_string()))
+            .collect(),
+    };
+    serde_json::to_string_pretty(&document).map_err(json_error)
+}
+
+pub fn from_json(json: &str) -> Result<Sketch, DocumentError> {
+    let value: Value = serde_json::from_str(json).map_err(json_error)?;
+    let document: Document = serde_json::from_value(migrate(value)?).map_err(json_error)?;
+
+    if let Some((id, _)) = document
+        .bindings
+        .iter()
+        .find(|(id, bind)| bind.get_id() != **id)
+    {
+        return Err(DocumentError::Invalid(format!(
+            "binding {} is stored under another id",
+            **id
+        )));
+    }
+    let invalid = |e: String| DocumentError::Invalid(e);
+    let mut sketch = Sketch::from_pools(document.vertices, document.shapes, document.bindings)
+        .map_err(|e| invalid(e.to_string()))?;
+    let mut solved = vec![];
+    for entry in document.parameters.iter() {
+        sketch
+            .set_parameter(&entry.name, &entry.source)
+            .map_err(|e| invalid(e.to_string()))?;
+        if entry.unknown {
+            sketch
+                .set_parameter_unknown(&entry.name, true)
+                .map_err(|e| invalid(e.to_string()))?;
+            solved.push((entry.name.clone(), entry.value));
+        }
+    }
+    sketch
+        .set_parameters_solved(&solved)
+        .map_err(|e| invalid(e.to_string()))?;
+    for (id, source) in document.expressions.iter() {
+        sketch
+            .set_binding_expr(id, source)
+            .map_err(|e| invalid(format!("binding {}: {e}", **id)))?;
+    }
+    Ok(sketch)
+}
+
+// Bring a document of any supported version to the current one. Each change of
+// the format bumps VERSION and adds the step from the previous version here
+fn migrate(document: Value) -> Result<Value, DocumentError> {
+    let version = document
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or(DocumentError::MissingVersion)?;
+    match version {
+        VERSION => Ok(document),
+        _ => Err(DocumentError::UnsupportedVersion(version)),
+    }
+}
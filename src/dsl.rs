@@ -0,0 +1,603 @@
+// Text format of the sketches, one statement per line, '#' starts a comment:
+//
+//   param w = 40
+//   unknown r = 5                  solved along the vertices, see Parameters::set_unknown
+//   point a = (-27, 30)
+//   bounds a = (-100, -100) (100, 100)
+//   line (a, b)
+//   fixed a                        at its position, or fixed a = (x, y)
+//   fixed_x b = 20                 fixed_y likewise, at its position without value
+//   vertical (c, d)                horizontal likewise
+//   parallel (a, b) (c, d)         perpendicular and equal likewise
+//   distance a d = w * 3 + 1       at the current distance without value
+//   coincident a b
+//   distance a b in [1, 5]
+//   angle (a, b) (c, d) in [0, 1.5]
+//   half_plane v (a, b) = -1
+//
+// Values are numbers or expressions of the parameters, angles are in radians.
+// A binding can be preceded by the modifiers reference, disabled, strong, medium,
+// weak and weight w.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use kurbo::{Point, Rect};
+
+use crate::math::*;
+use crate::params::{lex, Expr, Located, ParamError, Token};
+use crate::sketch::{PoolsError, Sketch};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DslError {
+    // 1-based, the column counts characters
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+fn tokenize(text: &str, line: usize) -> Result<Vec<Located>, DslError> {
+    lex(text).map_err(|e| DslError {
+        line,
+        column: e.column,
+        message: format!("invalid number {}", e.number),
+    })
+}
+
+// The statements are read into the pools, the parameters and the expressions
+// driving the values are set on the sketch built from them
+struct Reader {
+    vertices: VerticesPool,
+    shapes: ShapesPool,
+    bindings: BindingsPool,
+    names: BTreeMap<String, VertexId>,
+    // (line, column, name, source, unknown)
+    parameters: Vec<(usize, usize, String, String, bool)>,
+    // (line, column, binding, source)
+    expressions: Vec<(usize, usize, BindingId, String)>,
+    // (line, column) of the statements of the bindings and of the shapes
+    binding_statements: BTreeMap<BindingId, (usize, usize)>,
+    shape_statements: BTreeMap<ShapeTypeId, (usize, usize)>,
+}
+
+// Parser of the tokens of a line
+struct Line<'a> {
+    text: &'a str,
+    line: usize,
+    tokens: Vec<Located>,
+    pos: usize,
+}
+impl<'a> Line<'a> {
+    fn error<T>(&self, message: String) -> Result<T, DslError> {
+        let column = match self.tokens.get(self.pos) {
+            Some(located) => located.column,
+            None => self.text.chars().count() + 1,
+        };
+        Err(DslError {
+            line: self.line,
+            column,
+            message,
+        })
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|located| &located.token)
+    }
+    fn eat(&mut self, sym: char) -> bool {
+        if self.peek() == Some(&Token::Sym(sym)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, sym: char) -> Result<(), DslError> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{sym}'"))
+        }
+    }
+    fn ident(&mut self) -> Result<String, DslError> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => self.error("expected a name".to_string()),
+        }
+    }
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.peek() == Some(&Token::Ident(keyword.to_string())) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn number(&mut self) -> Result<f64, DslError> {
+        let sign = if self.eat('-') { -1. } else { 1. };
+        let value = match self.peek() {
+            Some(Token::Num(value)) => *value,
+            Some(Token::Ident(ident)) if ident == "inf" => f64::INFINITY,
+            _ => return self.error("expected a number".to_string()),
+        };
+        self.pos += 1;
+        Ok(sign * value)
+    }
+    fn point(&mut self) -> Result<Point, DslError> {
+        self.expect('(')?;
+        let x = self.number()?;
+        self.expect(',')?;
+        let y = self.number()?;
+        self.expect(')')?;
+        Ok(Point::new(x, y))
+    }
+    fn range(&mut self) -> Result<(f64, f64), DslError> {
+        self.expect('[')?;
+        let min = self.number()?;
+        self.expect(',')?;
+        let max = self.number()?;
+        self.expect(']')?;
+        Ok((min, max))
+    }
+    fn vertex(&mut self, names: &BTreeMap<String, VertexId>) -> Result<VertexId, DslError> {
+        let name = self.ident()?;
+        match names.get(&name) {
+            Some(v_id) => Ok(*v_id),
+            None => {
+                self.pos -= 1;
+                self.error(format!("unknown point {name}"))
+            }
+        }
+    }
+    fn segment(
+        &mut self,
+        names: &BTreeMap<String, VertexId>,
+    ) -> Result<(VertexId, VertexId), DslError> {
+        self.expect('(')?;
+        let va = self.vertex(names)?;
+        self.expect(',')?;
+        let vb = self.vertex(names)?;
+        self.expect(')')?;
+        Ok((va, vb))
+    }
+    // The source after '=' up to the end of the line, with its column
+    fn rest(&mut self) -> Result<(usize, String), DslError> {
+        match self.tokens.get(self.pos) {
+            Some(located) => {
+                let rest = (
+                    located.column,
+                    self.text[located.offset..].trim().to_string(),
+                );
+                self.pos = self.tokens.len();
+                Ok(rest)
+            }
+            None => self.error("expected a value".to_string()),
+        }
+    }
+    fn end(&self) -> Result<(), DslError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.error("unexpected text at the end of the statement".to_string()),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Sketch, DslError> {
+    let mut reader = Reader {
+        vertices: VerticesPool::new(),
+        shapes: ShapesPool::new(),
+        bindings: BindingsPool::new(),
+        names: BTreeMap::new(),
+        parameters: vec![],
+        expressions: vec![],
+        binding_statements: BTreeMap::new(),
+        shape_statements: BTreeMap::new(),
+    };
+    for (idx, text) in text.lines().enumerate() {
+        let text = text.split('#').next().unwrap();
+        let mut line = Line {
+            text,
+            line: idx + 1,
+            tokens: tokenize(text, idx + 1)?,
+            pos: 0,
+        };
+        if line.tokens.is_empty() {
+            continue;
+        }
+        reader.statement(&mut line)?;
+    }
+
+    let error = |line, column, message| DslError {
+        line,
+        column,
+        message,
+    };
+    let (bindings_at, shapes_at) = (reader.binding_statements, reader.shape_statements);
+    let mut sketch =
+        Sketch::from_pools(reader.vertices, reader.shapes, reader.bindings).map_err(|e| {
+            let (line, column) = match &e {
+                PoolsError::Dangling(dangling) => bindings_at[&dangling[0].binding_id],
+                PoolsError::Binding(id, _) => bindings_at[id],
+                PoolsError::Shape(id) => shapes_at[id],
+            };
+            error(line, column, e.to_string())
+        })?;
+    for (line, column, name, source, unknown) in reader.parameters {
+        sketch
+            .set_parameter(&name, &source)
+            .map_err(|e| error(line, column, e.to_string()))?;
+        if unknown {
+            sketch
+                .set_parameter_unknown(&name, true)
+                .map_err(|e| error(line, column, e.to_string()))?;
+        }
+    }
+    for (line, column, id, source) in reader.expressions {
+        sketch
+            .set_binding_expr(&id, &source)
+            .map_err(|e| error(line, column, e.to_string()))?;
+    }
+    Ok(sketch)
+}
+
+impl Reader {
+    fn statement(&mut self, line: &mut Line) -> Result<(), DslError> {
+        let start = line.tokens[0].column;
+        let keyword = line.ident()?;
+        match keyword.as_str() {
+            "param" | "unknown" => {
+                let name = line.ident()?;
+                line.expect('=')?;
+                let (column, source) = line.rest()?;
+                self.parameters
+                    .push((line.line, column, name, source, keyword == "unknown"));
+            }
+            "point" => {
+                let name = line.ident()?;
+                if self.names.contains_key(&name) {
+                    line.pos -= 1;
+                    return line.error(format!("point {name} is already defined"));
+                }
+                line.expect('=')?;
+                let pt = line.point()?;
+                line.end()?;
+                self.names.insert(name, self.vertices.add(pt).id);
+            }
+            "bounds" => {
+                let v_id = line.vertex(&self.names)?;
+                line.expect('=')?;
+                let (p0, p1) = (line.point()?, line.point()?);
+                line.end()?;
+                self.vertices
+                    .set_bounds(&v_id, Some(Rect::from_points(p0, p1)));
+            }
+            "line" => {
+                let (va, vb) = line.segment(&self.names)?;
+                line.end()?;
+                let shape = self
+                    .shapes
+                    .add_line(&self.vertices[&va], &self.vertices[&vb]);
+                self.shape_statements.insert(shape.id, (line.line, start));
+            }
+            _ => {
+                line.pos -= 1;
+                let props = Reader::modifiers(line)?;
+                let id = self.binding(line, props)?.map_err(|e| DslError {
+                    line: line.line,
+                    column: start,
+                    message: e.to_string(),
+                })?;
+                self.binding_statements.insert(id, (line.line, start));
+            }
+        }
+        Ok(())
+    }
+
+    fn modifiers(line: &mut Line) -> Result<BindProps, DslError> {
+        let mut props = BindProps::default();
+        loop {
+            if line.keyword("reference") {
+                props.reference = true;
+            } else if line.keyword("disabled") {
+                props.enabled = false;
+            } else if line.keyword("strong") {
+                props.strength = Strength::Strong;
+            } else if line.keyword("medium") {
+                props.strength = Strength::Medium;
+            } else if line.keyword("weak") {
+                props.strength = Strength::Weak;
+            } else if line.keyword("weight") {
+                props.strength = Strength::Weight(line.number()?);
+            } else {
+                return Ok(props);
+            }
+        }
+    }
+
+    // The count values of a binding after '=', numbers or expressions set once the
+    // parameters are known, a point (x, y) for two. None without value
+    fn values(
+        &mut self,
+        line: &mut Line,
+        id: BindingId,
+        count: usize,
+    ) -> Result<Option<Vec<f64>>, DslError> {
+        if !line.eat('=') {
+            line.end()?;
+            return Ok(None);
+        }
+        let (column, source) = line.rest()?;
+        let exprs = Expr::parse_values(&source, count).map_err(|e| DslError {
+            line: line.line,
+            column,
+            message: e.to_string(),
+        })?;
+        let mut used = BTreeSet::new();
+        exprs.iter().for_each(|expr| expr.params(&mut used));
+        if used.is_empty() {
+            let values: Result<Vec<f64>, _> = exprs
+                .iter()
+                .map(|expr| expr.eval(&BTreeMap::new()))
+                .collect();
+            if let Ok(values) = values {
+                return Ok(Some(values));
+            }
+        }
+        self.expressions.push((line.line, column, id, source));
+        Ok(None)
+    }
+
+    // The binding is built aside and added with its modifiers, so that it is
+    // checked as it is, e.g. a reference isn't the duplicate of a driving binding
+    fn binding(
+        &mut self,
+        line: &mut Line,
+        props: BindProps,
+    ) -> Result<Result<BindingId, BindingError>, DslError> {
+        let keyword = line.ident()?;
+        let names = &self.names;
+        let v = |v_id: VertexId| self.vertices[&v_id];
+        let mut built = BindingsPool::new();
+        let bindings = &mut built;
+        // The values after '=' are read once the binding is added, as expressions
+        // refer to it by id
+        let (added, count) = match keyword.as_str() {
+            "fixed" => {
+                let v_id = line.vertex(names)?;
+                (bindings.add_bind_fixed(&v(v_id)).map(|b| b.id), 2)
+            }
+            "fixed_x" | "fixed_y" | "distance" => {
+                let va = line.vertex(names)?;
+                match keyword.as_str() {
+                    "fixed_x" => (bindings.add_bind_fixed_x(&v(va)).map(|b| b.id), 1),
+                    "fixed_y" => (bindings.add_bind_fixed_y(&v(va)).map(|b| b.id), 1),
+                    _ => {
+                        let vb = line.vertex(names)?;
+                        if line.keyword("in") {
+                            let (min, max) = line.range()?;
+                            line.end()?;
+                            let added = bindings
+                                .add_bind_distance_range((&v(va), &v(vb)), min, max)
+                                .map(|b| b.id);
+                            (added, 0)
+                        } else {
+                            (
+                                bindings.add_bind_distance((&v(va), &v(vb))).map(|b| b.id),
+                                1,
+                            )
+                        }
+                    }
+                }
+            }
+            "vertical" | "horizontal" => {
+                let (va, vb) = line.segment(names)?;
+                line.end()?;
+                let seg = (&v(va), &v(vb));
+                let added = match keyword.as_str() {
+                    "vertical" => bindings.add_bind_vertical(seg).map(|b| b.id),
+                    _ => bindings.add_bind_horizontal(seg).map(|b| b.id),
+                };
+                (added, 0)
+            }
+            "parallel" | "perpendicular" | "equal" | "angle" => {
+                let (l1va, l1vb) = line.segment(names)?;
+                let (l2va, l2vb) = line.segment(names)?;
+                let (seg1, seg2) = ((&v(l1va), &v(l1vb)), (&v(l2va), &v(l2vb)));
+                if keyword == "angle" {
+                    if !line.keyword("in") {
+                        return line.error("expected 'in'".to_string());
+                    }
+                    let (min, max) = line.range()?;
+                    line.end()?;
+                    let added = bindings
+                        .add_bind_angle_range(seg1, seg2, min, max)
+                        .map(|b| b.id);
+                    (added, 0)
+                } else {
+                    line.end()?;
+                    let added = match keyword.as_str() {
+                        "parallel" => bindings.add_bind_parallel(seg1, seg2).map(|b| b.id),
+                        "perpendicular" => {
+                            bindings.add_bind_perpendicular(seg1, seg2).map(|b| b.id)
+                        }
+                        _ => bindings.add_bind_equal_length(seg1, seg2).map(|b| b.id),
+                    };
+                    (added, 0)
+                }
+            }
+            "coincident" => {
+                let (va, vb) = (line.vertex(names)?, line.vertex(names)?);
+                line.end()?;
+                (
+                    bindings.add_bind_coincident(&v(va), &v(vb)).map(|b| b.id),
+                    0,
+                )
+            }
+            "half_plane" => {
+                let v_id = line.vertex(names)?;
+                let (la, lb) = line.segment(names)?;
+                let side = if line.eat('=') {
+                    Some(line.number()?)
+                } else {
+                    None
+                };
+                line.end()?;
+                let added = bindings.add_bind_half_plane(&v(v_id), (&v(la), &v(lb)));
+                if let (Ok(b), Some(side)) = (&added, side) {
+                    if let Some(Binding::HalfPlane(b)) = bindings.get_mut(&b.id) {
+                        b.side = side;
+                    }
+                }
+                (added.map(|b| b.id), 0)
+            }
+            _ => {
+                line.pos -= 1;
+                return line.error(format!("unknown statement {keyword}"));
+            }
+        };
+        let mut bind = match added {
+            Ok(id) => built.remove_binding(&id).unwrap(),
+            Err(e) => return Ok(Err(e)),
+        };
+        *bind.props_mut() = props;
+        let id = match self.bindings.add_binding(bind, &self.vertices) {
+            Ok(id) => id,
+            Err(e) => return Ok(Err(e)),
+        };
+        if count > 0 {
+            if let Some(values) = self.values(line, id, count)? {
+                self.bindings.get_mut(&id).unwrap().set_values(&values);
+            }
+        }
+        Ok(Ok(id))
+    }
+}
+
+// The sketch in the text format, the points named after their ids. Custom
+// bindings can't be written and are left as comments. The parameters are written
+// in the order of their dependencies, an error when there is none
+pub fn print(sketch: &Sketch) -> Result<String, ParamError> {
+    let name = |v_id: &VertexId| format!("p{}", **v_id);
+    let seg = |va: &VertexId, vb: &VertexId| format!("({}, {})", name(va), name(vb));
+    let mut lines = vec![];
+
+    let parameters = sketch.parameters();
+    parameters.order()?.iter().for_each(|param_name| {
+        let param = &parameters[param_name];
+        lines.push(match param.unknown {
+            true => format!("unknown {param_name} = {}", param.value),
+            false => format!("param {param_name} = {}", param.source),
+        })
+    });
+    sketch.vertices().values().for_each(|v| {
+        lines.push(format!("point {} = ({}, {})", name(&v.id), v.pt.x, v.pt.y));
+    });
+    sketch.vertices().values().for_each(|v| {
+        if let Some(rect) = v.bounds {
+            lines.push(format!(
+                "bounds {} = ({}, {}) ({}, {})",
+                name(&v.id),
+                rect.x0,
+                rect.y0,
+                rect.x1,
+                rect.y1
+            ));
+        }
+    });
+    sketch.shapes().values().for_each(|shape| match shape {
+        ShapeType::STLine(line) => lines.push(format!("line {}", seg(&line.va_id, &line.vb_id))),
+    });
+
+    sketch.bindings().values().for_each(|bind| {
+        let value = |value: f64| match sketch.binding_expr(&bind.get_id()) {
+            Some(source) => source.to_string(),
+            None => value.to_string(),
+        };
+        let statement = match bind {
+            Binding::Fixed(b) => match sketch.binding_expr(&b.id) {
+                Some(source) => format!("fixed {} = {source}", name(&b.v_id)),
+                None => format!(
+                    "fixed {} = ({}, {})",
+                    name(&b.v_id),
+                    b.fixed_value.x,
+                    b.fixed_value.y
+                ),
+            },
+            Binding::FixedX(b) => format!("fixed_x {} = {}", name(&b.v_id), value(b.fixed_value)),
+            Binding::FixedY(b) => format!("fixed_y {} = {}", name(&b.v_id), value(b.fixed_value)),
+            Binding::Vertical(b) => format!("vertical {}", seg(&b.va_id, &b.vb_id)),
+            Binding::Horizontal(b) => format!("horizontal {}", seg(&b.va_id, &b.vb_id)),
+            Binding::Parallel(b) => format!(
+                "parallel {} {}",
+                seg(&b.l1va_id, &b.l1vb_id),
+                seg(&b.l2va_id, &b.l2vb_id)
+            ),
+            Binding::Distance(b) => format!(
+                "distance {} {} = {}",
+                name(&b.va_id),
+                name(&b.vb_id),
+                value(b.sq_distance_value.sqrt())
+            ),
+            Binding::Perpendicular(b) => format!(
+                "perpendicular {} {}",
+                seg(&b.l1va_id, &b.l1vb_id),
+                seg(&b.l2va_id, &b.l2vb_id)
+            ),
+            Binding::EqualLength(b) => format!(
+                "equal {} {}",
+                seg(&b.l1va_id, &b.l1vb_id),
+                seg(&b.l2va_id, &b.l2vb_id)
+            ),
+            Binding::Coincident(b) => format!("coincident {} {}", name(&b.va_id), name(&b.vb_id)),
+            Binding::DistanceRange(b) => format!(
+                "distance {} {} in [{}, {}]",
+                name(&b.va_id),
+                name(&b.vb_id),
+                b.min_value,
+                b.max_value
+            ),
+            Binding::HalfPlane(b) => format!(
+                "half_plane {} {} = {}",
+                name(&b.v_id),
+                seg(&b.la_id, &b.lb_id),
+                b.side
+            ),
+            Binding::AngleRange(b) => format!(
+                "angle {} {} in [{}, {}]",
+                seg(&b.l1va_id, &b.l1vb_id),
+                seg(&b.l2va_id, &b.l2vb_id),
+                b.min_angle,
+                b.max_angle
+            ),
+            Binding::Custom(b) => {
+                lines.push(format!("# custom binding {} can't be written", *b.id));
+                return;
+            }
+        };
+        let props = bind.get_props();
+        let mut modifiers = vec![];
+        if props.reference {
+            modifiers.push("reference".to_string());
+        }
+        if !props.enabled {
+            modifiers.push("disabled".to_string());
+        }
+        match props.strength {
+            Strength::Required => (),
+            Strength::Strong => modifiers.push("strong".to_string()),
+            Strength::Medium => modifiers.push("medium".to_string()),
+            Strength::Weak => modifiers.push("weak".to_string()),
+            Strength::Weight(w) => modifiers.push(format!("weight {w}")),
+        }
+        modifiers.push(statement);
+        lines.push(modifiers.join(" "));
+    });
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,592 @@
+// ASCII DXF drawings: the LINE, CIRCLE, ARC, LWPOLYLINE and POINT entities are
+// read as vertices and line shapes, the distance and angle bindings are written
+// as DIMENSION entities and the fixed bindings as POINT entities, and read back.
+// Their reference and disabled flags and the fixed values are kept in the
+// extended data (XDATA) of the entities
+use std::collections::BTreeSet;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::fmt::Write;
+
+use kurbo::{Arc, BezPath, Circle, Point, Shape, Vec2};
+
+use crate::import::{ImportOptions, Importer};
+use crate::math::*;
+use crate::sketch::Sketch;
+
+// The application of the extended data, registered in the APPID table
+const APP: &str = "TEST_GOMEZ";
+// The flags of the extended data
+const REFERENCE: i64 = 1;
+const DISABLED: i64 = 2;
+
+// An entity as its group codes and values, in the file order
+struct Entity<'a> {
+    kind: &'a str,
+    line: usize,
+    groups: Vec<(i32, &'a str)>,
+}
+impl Entity<'_> {
+    fn value(&self, code: i32) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, value)| *value)
+    }
+    fn number(&self, code: i32) -> Result<f64, String> {
+        let value = self
+            .value(code)
+            .ok_or_else(|| format!("line {}: {} without group {code}", self.line, self.kind))?;
+        parse_number(value, code, self.line)
+    }
+    fn number_or(&self, code: i32, default: f64) -> Result<f64, String> {
+        match self.value(code) {
+            Some(value) => parse_number(value, code, self.line),
+            None => Ok(default),
+        }
+    }
+    // The point of the x code, whose y code is the x code + 10
+    fn point(&self, code: i32) -> Result<Point, String> {
+        Ok(Point::new(self.number(code)?, self.number(code + 10)?))
+    }
+    // The extended data of the program, empty when written by another one
+    fn xdata(&self) -> &[(i32, &str)] {
+        let Some(start) = self.groups.iter().position(|group| *group == (1001, APP)) else {
+            return &[];
+        };
+        let xdata = &self.groups[start + 1..];
+        let end = xdata
+            .iter()
+            .position(|(code, _)| *code == 1001)
+            .unwrap_or(xdata.len());
+        &xdata[..end]
+    }
+    // The binding with the flags and the values of the extended data, added to
+    // the sketch
+    fn add(&self, sketch: &mut Sketch, mut bind: Binding) -> Result<(), String> {
+        let mut values = vec![];
+        for (code, value) in self.xdata() {
+            let number = || parse_number(value, *code, self.line);
+            match code {
+                1070 => {
+                    let flags = number()? as i64;
+                    let props = bind.props_mut();
+                    props.reference = flags & REFERENCE != 0;
+                    props.enabled = flags & DISABLED == 0;
+                }
+                1040 => values.push(number()?),
+                _ => (),
+            }
+        }
+        if !values.is_empty() && !bind.set_values(&values) {
+            return Err(format!(
+                "line {}: invalid values of {}",
+                self.line, self.kind
+            ));
+        }
+        sketch.add_binding(bind).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+// The binding added to a scratch pool, for its flags and values to be set
+// before it is added to the sketch
+fn built<T>(
+    add: impl FnOnce(&mut BindingsPool) -> Result<T, BindingError>,
+    id: impl FnOnce(&T) -> BindingId,
+) -> Result<Binding, String> {
+    let mut pool = BindingsPool::new();
+    let added = add(&mut pool).map_err(|e| e.to_string())?;
+    Ok(pool.remove_binding(&id(&added)).unwrap())
+}
+
+fn parse_number(value: &str, code: i32, line: usize) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("line {line}: invalid value {value} of group {code}"))
+}
+
+// The entities of the ENTITIES section, the other sections are skipped
+fn entities(text: &str) -> Result<Vec<Entity<'_>>, String> {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let mut groups = vec![];
+    for (i, pair) in lines.chunks(2).enumerate() {
+        match pair {
+            [code, value] => {
+                let code = code
+                    .parse::<i32>()
+                    .map_err(|_| format!("line {}: invalid group code {code}", 2 * i + 1))?;
+                groups.push((2 * i + 1, code, *value));
+            }
+            // Some writers end the file with a newline too many
+            [""] => (),
+            _ => return Err(format!("line {}: group code without value", 2 * i + 1)),
+        }
+    }
+    let mut entities = vec![];
+    let mut in_entities = false;
+    let mut groups = groups.into_iter().peekable();
+    while let Some((line, code, value)) = groups.next() {
+        match (code, value) {
+            (0, "SECTION") => {
+                in_entities = matches!(groups.peek(), Some((_, 2, "ENTITIES")));
+            }
+            (0, "ENDSEC") => in_entities = false,
+            (0, kind) if in_entities => {
+                let mut entity = Entity {
+                    kind,
+                    line,
+                    groups: vec![],
+                };
+                while let Some((_, code, value)) = groups.next_if(|(_, code, _)| *code != 0) {
+                    entity.groups.push((code, value));
+                }
+                entities.push(entity);
+            }
+            _ => (),
+        }
+    }
+    Ok(entities)
+}
+
+// The flattened arc from p0 to p1 of a polyline segment, the bulge being the
+// tangent of the quarter of its signed sweep angle
+fn bulge_path(p0: Point, p1: Point, bulge: f64, tolerance: f64) -> BezPath {
+    let chord = p1 - p0;
+    let center =
+        p0.midpoint(p1) + Vec2::new(-chord.y, chord.x) * (1. - bulge.powi(2)) / (4. * bulge);
+    let radius = center.distance(p0);
+    let arc = Arc {
+        center,
+        radii: Vec2::new(radius, radius),
+        start_angle: (p0 - center).atan2(),
+        sweep_angle: 4. * bulge.atan(),
+        x_rotation: 0.,
+    };
+    arc.to_path(tolerance)
+}
+
+fn polyline(importer: &mut Importer, entity: &Entity, tolerance: f64) -> Result<(), String> {
+    let closed = entity.number_or(70, 0.)? as i64 & 1 == 1;
+    // The vertices start at their x code, the bulge of the segment to the next
+    // vertex comes after
+    let mut vertices: Vec<(Point, f64)> = vec![];
+    for (code, value) in entity.groups.iter() {
+        let value = || parse_number(value, *code, entity.line);
+        match (code, vertices.last_mut()) {
+            (10, _) => vertices.push((Point::new(value()?, 0.), 0.)),
+            (20, Some((pt, _))) => pt.y = value()?,
+            (42, Some((_, bulge))) => *bulge = value()?,
+            _ => (),
+        }
+    }
+    if closed && vertices.len() > 2 {
+        vertices.push((vertices[0].0, 0.));
+    }
+    for pair in vertices.windows(2) {
+        let ((p0, bulge), (p1, _)) = (pair[0], pair[1]);
+        if bulge.abs() < 1e-9 {
+            importer.segment(p0, p1);
+        } else {
+            importer.path(&bulge_path(p0, p1, bulge, tolerance));
+        }
+    }
+    Ok(())
+}
+
+// The vertex at the point, if any
+fn vertex_at(sketch: &Sketch, pt: Point, merge_distance: f64) -> Option<Vertex> {
+    sketch
+        .vertices()
+        .values()
+        .find(|v| v.pt.distance(pt) <= merge_distance)
+        .copied()
+}
+
+// The aligned dimensions become distance bindings and the angular ones angle
+// bindings, when their points are vertices of the drawing. A linear dimension is
+// a distance binding when its points are along its rotation, else a horizontal
+// or a vertical one is measured to a new vertex at the corner; the other ones
+// are skipped
+fn dimension(sketch: &mut Sketch, entity: &Entity, opts: &ImportOptions) -> Result<(), String> {
+    let at = |pt| vertex_at(sketch, pt, opts.merge_distance);
+    let kind = entity.number_or(70, 0.)? as i64 & 7;
+    match kind {
+        // Linear and aligned
+        0 | 1 => {
+            let (Some(va), Some(mut vb)) = (at(entity.point(13)?), at(entity.point(14)?)) else {
+                return Ok(());
+            };
+            if kind == 0 {
+                let rotation = entity.number_or(50, 0.)?.to_radians();
+                let direction = Vec2::from_angle(rotation);
+                if direction.cross(vb.pt - va.pt).abs() > opts.merge_distance {
+                    // The span along an axis, from the first point to the corner
+                    // under or beside the second one
+                    let quarters = rotation / FRAC_PI_2;
+                    if (quarters - quarters.round()).abs() > 1e-9 {
+                        return Ok(());
+                    }
+                    let horizontal = quarters.round() as i64 % 2 == 0;
+                    let corner = match horizontal {
+                        true => Point::new(vb.pt.x, va.pt.y),
+                        false => Point::new(va.pt.x, vb.pt.y),
+                    };
+                    if corner.distance(va.pt) <= opts.merge_distance {
+                        return Ok(());
+                    }
+                    let vc = sketch.add_vertex(corner);
+                    let (first, second) = ((&va, &vc), (&vc, &vb));
+                    let added = match horizontal {
+                        true => sketch
+                            .add_bind_horizontal(first)
+                            .and_then(|_| sketch.add_bind_vertical(second).map(|b| b.id)),
+                        false => sketch
+                            .add_bind_vertical(first)
+                            .and_then(|_| sketch.add_bind_horizontal(second).map(|b| b.id)),
+                    };
+                    added.map_err(|e| e.to_string())?;
+                    vb = vc;
+                }
+            }
+            let mut bind = built(|pool| pool.add_bind_distance((&va, &vb)), |b| b.id)?;
+            if entity.value(42).is_some() {
+                bind.set_value(entity.number(42)?);
+            }
+            entity.add(sketch, bind)?;
+        }
+        // Two lines, from 13 to 14 and from 15 to 10
+        2 => {
+            let points = [
+                at(entity.point(13)?),
+                at(entity.point(14)?),
+                at(entity.point(15)?),
+                at(entity.point(10)?),
+            ];
+            let [Some(l1va), Some(l1vb), Some(l2va), Some(l2vb)] = points else {
+                return Ok(());
+            };
+            let (d1, d2) = (l1vb.pt - l1va.pt, l2vb.pt - l2va.pt);
+            let current = d1.cross(d2).atan2(d1.dot(d2));
+            let value = match entity.value(42) {
+                Some(_) => entity.number(42)?,
+                None => current.abs(),
+            };
+            let (seg1, seg2) = ((&l1va, &l1vb), (&l2va, &l2vb));
+            let bind = if (value - FRAC_PI_2).abs() < 1e-9 {
+                built(|pool| pool.add_bind_perpendicular(seg1, seg2), |b| b.id)?
+            } else {
+                // The measurement is unsigned, the drawing gives the side
+                let angle = value.copysign(current);
+                built(
+                    |pool| pool.add_bind_angle_range(seg1, seg2, angle, angle),
+                    |b| b.id,
+                )?
+            };
+            entity.add(sketch, bind)?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// The points of the program are fixed bindings of the kind of their extended
+// data
+fn fixed(sketch: &mut Sketch, entity: &Entity, opts: &ImportOptions) -> Result<(), String> {
+    let kind = entity
+        .xdata()
+        .iter()
+        .find(|(code, _)| *code == 1000)
+        .map(|(_, kind)| *kind);
+    let Some(kind) = kind else {
+        return Ok(());
+    };
+    let Some(v) = vertex_at(sketch, entity.point(10)?, opts.merge_distance) else {
+        return Ok(());
+    };
+    let bind = match kind {
+        "FIXED" => built(|pool| pool.add_bind_fixed(&v), |b| b.id)?,
+        "FIXED_X" => built(|pool| pool.add_bind_fixed_x(&v), |b| b.id)?,
+        "FIXED_Y" => built(|pool| pool.add_bind_fixed_y(&v), |b| b.id)?,
+        _ => return Err(format!("line {}: unknown binding {kind}", entity.line)),
+    };
+    entity.add(sketch, bind)
+}
+
+// Sketch of the lines, circles, arcs, light polylines and points of an ASCII DXF
+// drawing, and of the bindings of its dimensions. The coordinates are kept,
+// the y axis pointing up
+pub fn import(text: &str, opts: &ImportOptions) -> Result<Sketch, String> {
+    let entities = entities(text)?;
+    let mut importer = Importer::new(opts);
+    for entity in entities.iter() {
+        match entity.kind {
+            "LINE" => importer.segment(entity.point(10)?, entity.point(11)?),
+            "CIRCLE" => {
+                let circle = Circle::new(entity.point(10)?, entity.number(40)?);
+                importer.path(&circle.to_path(opts.tolerance));
+            }
+            "ARC" => {
+                let radius = entity.number(40)?;
+                let start = entity.number(50)?.to_radians();
+                let end = entity.number(51)?.to_radians();
+                // Counterclockwise from the start angle to the end angle
+                let sweep = (end - start).rem_euclid(2. * PI);
+                let arc = Arc {
+                    center: entity.point(10)?,
+                    radii: Vec2::new(radius, radius),
+                    start_angle: start,
+                    sweep_angle: if sweep == 0. { 2. * PI } else { sweep },
+                    x_rotation: 0.,
+                };
+                importer.path(&arc.to_path(opts.tolerance));
+            }
+            "LWPOLYLINE" => polyline(&mut importer, entity, opts.tolerance)?,
+            "POINT" => {
+                importer.vertex(entity.point(10)?);
+            }
+            _ => (),
+        }
+    }
+    // After the geometry, whose vertices the dimensions refer to
+    let mut sketch = importer.sketch();
+    for entity in entities.iter() {
+        match entity.kind {
+            "DIMENSION" => dimension(&mut sketch, entity, opts)?,
+            "POINT" => fixed(&mut sketch, entity, opts)?,
+            _ => (),
+        }
+    }
+    Ok(sketch)
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+}
+impl Writer {
+    fn group(&mut self, code: i32, value: impl std::fmt::Display) {
+        writeln!(self.out, "{code:>3}\n{value}").unwrap();
+    }
+    fn point(&mut self, code: i32, pt: Point) {
+        self.group(code, pt.x);
+        self.group(code + 10, pt.y);
+    }
+    fn entity(&mut self, kind: &str) {
+        self.group(0, kind);
+        self.group(8, "0");
+    }
+    fn section(&mut self, name: &str, content: &str) {
+        self.group(0, "SECTION");
+        self.group(2, name);
+        self.out.push_str(content);
+        self.group(0, "ENDSEC");
+    }
+    fn table(&mut self, kind: &str, entries: &[&[(i32, &str)]]) {
+        self.group(0, "TABLE");
+        self.group(2, kind);
+        self.group(70, entries.len());
+        for entry in entries {
+            self.group(0, kind);
+            entry
+                .iter()
+                .for_each(|(code, value)| self.group(*code, value));
+        }
+        self.group(0, "ENDTAB");
+    }
+    fn text(&mut self, pt: Point, height: f64, text: &str) {
+        self.entity("TEXT");
+        self.point(10, pt);
+        self.group(40, height);
+        self.group(1, text);
+    }
+    // The extended data of the binding, its flags and the fixed values
+    fn xdata(&mut self, bind: &Binding, kind: Option<&str>) {
+        let props = bind.get_props();
+        self.group(1001, APP);
+        if let Some(kind) = kind {
+            self.group(1000, kind);
+        }
+        let flags = match props.reference {
+            true => REFERENCE,
+            false => 0,
+        } | match props.enabled {
+            true => 0,
+            false => DISABLED,
+        };
+        self.group(1070, flags);
+        if kind.is_some() {
+            bind.values()
+                .into_iter()
+                .for_each(|value| self.group(1040, value));
+        }
+    }
+}
+
+// The DIMENSION entities and the anonymous blocks drawing them
+#[derive(Default)]
+struct Dimensions {
+    blocks: Writer,
+    entities: Writer,
+    count: usize,
+}
+impl Dimensions {
+    // The entity up to its definition points, the block drawn with the text at
+    // the point
+    fn dimension(
+        &mut self,
+        kind: i32,
+        value: f64,
+        text: &str,
+        at: Point,
+        height: f64,
+    ) -> &mut Writer {
+        self.count += 1;
+        let name = format!("*D{}", self.count);
+        let blocks = &mut self.blocks;
+        blocks.entity("BLOCK");
+        blocks.group(2, &name);
+        blocks.group(70, 1);
+        blocks.point(10, Point::ZERO);
+        blocks.group(3, &name);
+        blocks.text(at, height, text);
+        blocks.entity("ENDBLK");
+        let entities = &mut self.entities;
+        entities.entity("DIMENSION");
+        entities.group(2, &name);
+        entities.group(70, kind);
+        entities.group(1, text);
+        entities.group(42, value);
+        entities
+    }
+}
+
+// ASCII DXF drawing of the line shapes, of the vertices not on a shape and of
+// the fixed bindings as points, and of the distance, perpendicular and fixed
+// angle bindings as dimensions, each one with the text of its value in its block.
+// The other bindings aren't written
+pub fn export(sketch: &Sketch) -> String {
+    let vertices = sketch.vertices();
+    let pt = |v_id: &VertexId| vertices[v_id].pt;
+    let mut entities = Writer::default();
+    let mut on_shapes = BTreeSet::new();
+    for shape in sketch.shapes().values() {
+        match shape {
+            ShapeType::STLine(line) => {
+                entities.entity("LINE");
+                entities.point(10, pt(&line.va_id));
+                entities.point(11, pt(&line.vb_id));
+                on_shapes.extend([line.va_id, line.vb_id]);
+            }
+        }
+    }
+    for v in vertices.values().filter(|v| !on_shapes.contains(&v.id)) {
+        entities.entity("POINT");
+        entities.point(10, v.pt);
+    }
+
+    let mut dimensions = Dimensions::default();
+    let angular =
+        |dimensions: &mut Dimensions, bind: &Binding, lines: [VertexId; 4], value: f64| {
+            let [l1va, l1vb, l2va, l2vb] = lines.map(|v_id| pt(&v_id));
+            // The arc goes through the middle of the four points
+            let middle = ((l1va.to_vec2() + l1vb.to_vec2() + l2va.to_vec2() + l2vb.to_vec2()) / 4.)
+                .to_point();
+            let text = format!("{:.2}%%d", value.to_degrees());
+            let height = l1va.distance(l1vb).min(l2va.distance(l2vb)) * 0.05;
+            let writer = dimensions.dimension(2, value, &text, middle, height);
+            writer.point(13, l1va);
+            writer.point(14, l1vb);
+            writer.point(15, l2va);
+            writer.point(10, l2vb);
+            writer.point(16, middle);
+            writer.xdata(bind, None);
+        };
+    for bind in sketch.bindings().values() {
+        match bind {
+            Binding::Fixed(b) => {
+                entities.entity("POINT");
+                entities.point(10, pt(&b.v_id));
+                entities.xdata(bind, Some("FIXED"));
+            }
+            Binding::FixedX(b) => {
+                entities.entity("POINT");
+                entities.point(10, pt(&b.v_id));
+                entities.xdata(bind, Some("FIXED_X"));
+            }
+            Binding::FixedY(b) => {
+                entities.entity("POINT");
+                entities.point(10, pt(&b.v_id));
+                entities.xdata(bind, Some("FIXED_Y"));
+            }
+            Binding::Distance(b) => {
+                let (pa, pb) = (pt(&b.va_id), pt(&b.vb_id));
+                let value = b.sq_distance_value.sqrt();
+                // The dimension line is drawn beside the segment, on its left
+                let offset = Vec2::new(pa.y - pb.y, pb.x - pa.x) * 0.1;
+                let text = format!("{value:.2}");
+                let at = pa.midpoint(pb) + offset;
+                let writer = dimensions.dimension(1, value, &text, at, offset.hypot() / 2.);
+                writer.point(10, pb + offset);
+                writer.point(13, pa);
+                writer.point(14, pb);
+                writer.xdata(bind, None);
+            }
+            Binding::Perpendicular(b) => angular(
+                &mut dimensions,
+                bind,
+                [b.l1va_id, b.l1vb_id, b.l2va_id, b.l2vb_id],
+                FRAC_PI_2,
+            ),
+            Binding::AngleRange(b) if b.min_angle == b.max_angle => angular(
+                &mut dimensions,
+                bind,
+                [b.l1va_id, b.l1vb_id, b.l2va_id, b.l2vb_id],
+                b.min_angle.abs(),
+            ),
+            _ => (),
+        }
+    }
+
+    let mut header = Writer::default();
+    header.group(9, "$ACADVER");
+    header.group(1, "AC1009");
+    let mut tables = Writer::default();
+    tables.table(
+        "LTYPE",
+        &[&[
+            (2, "CONTINUOUS"),
+            (70, "0"),
+            (3, "Solid line"),
+            (72, "65"),
+            (73, "0"),
+            (40, "0.0"),
+        ]],
+    );
+    tables.table(
+        "LAYER",
+        &[&[(2, "0"), (70, "0"), (62, "7"), (6, "CONTINUOUS")]],
+    );
+    tables.table(
+        "STYLE",
+        &[&[
+            (2, "STANDARD"),
+            (70, "0"),
+            (40, "0.0"),
+            (41, "1.0"),
+            (50, "0.0"),
+            (71, "0"),
+            (42, "1.0"),
+            (3, "txt"),
+            (4, ""),
+        ]],
+    );
+    tables.table("APPID", &[&[(2, APP), (70, "0")]]);
+    entities.out.push_str(&dimensions.entities.out);
+    let mut writer = Writer::default();
+    writer.section("HEADER", &header.out);
+    writer.section("TABLES", &tables.out);
+    writer.section("BLOCKS", &dimensions.blocks.out);
+    writer.section("ENTITIES", &entities.out);
+    writer.group(0, "EOF");
+    writer.out
+}
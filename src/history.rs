@@ -0,0 +1,668 @@
+// Undo and redo of the edits of a sketch. Each edit is recorded as a command
+// holding only what it changed in the pools, and is undone by its inverse
+use std::collections::BTreeMap;
+
+use kurbo::{Point, Rect};
+
+use crate::bindings::SolveOptions;
+use crate::math::*;
+use crate::params::ParamError;
+use crate::sketch::{RemovedVertex, Sketch};
+
+// A reversible edit of the sketch
+#[derive(Clone, Debug)]
+pub enum Command {
+    AddVertex(Vertex),
+    // Of a vertex without bindings or shapes, as the inverse of AddVertex
+    RemoveVertex(Vertex),
+    MoveVertex {
+        v_id: VertexId,
+        from: Point,
+        to: Point,
+    },
+    SetBounds {
+        v_id: VertexId,
+        from: Option<Rect>,
+        to: Option<Rect>,
+    },
+    AddShape(ShapeType),
+    RemoveShape(ShapeType),
+    // With the source of the expression driving the binding value, if any
+    AddBinding {
+        bind: Binding,
+        expr: Option<String>,
+    },
+    RemoveBinding {
+        bind: Binding,
+        expr: Option<String>,
+    },
+    // The values of a binding with dimensions, see Binding::values
+    SetValue {
+        id: BindingId,
+        from: Vec<f64>,
+        to: Vec<f64>,
+    },
+    SetEnabled {
+        id: BindingId,
+        from: bool,
+        to: bool,
+    },
+    SetReference {
+        id: BindingId,
+        from: bool,
+        to: bool,
+    },
+    SetStrength {
+        id: BindingId,
+        from: Strength,
+        to: Strength,
+    },
+    // The source of the expression driving the binding values, None without one
+    SetExpr {
+        id: BindingId,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    // The source of a parameter, None when it isn't defined
+    SetParameter {
+        name: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    // The value of an unknown parameter, as solved
+    SetUnknown {
+        name: String,
+        from: f64,
+        to: f64,
+    },
+}
+
+// What a command sets, see Step::push
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Vertex(VertexId),
+    Value(BindingId),
+    Unknown(String),
+}
+
+impl Command {
+    pub fn inverse(&self) -> Command {
+        match self.clone() {
+            Command::AddVertex(v) => Command::RemoveVertex(v),
+            Command::RemoveVertex(v) => Command::AddVertex(v),
+            Command::MoveVertex { v_id, from, to } => Command::MoveVertex {
+                v_id,
+                from: to,
+                to: from,
+            },
+            Command::SetBounds { v_id, from, to } => Command::SetBounds {
+                v_id,
+                from: to,
+                to: from,
+            },
+            Command::AddShape(shape) => Command::RemoveShape(shape),
+            Command::RemoveShape(shape) => Command::AddShape(shape),
+            Command::AddBinding { bind, expr } => Command::RemoveBinding { bind, expr },
+            Command::RemoveBinding { bind, expr } => Command::AddBinding { bind, expr },
+            Command::SetValue { id, from, to } => Command::SetValue {
+                id,
+                from: to,
+                to: from,
+            },
+            Command::SetEnabled { id, from, to } => Command::SetEnabled {
+                id,
+                from: to,
+                to: from,
+            },
+            Command::SetReference { id, from, to } => Command::SetReference {
+                id,
+                from: to,
+                to: from,
+            },
+            Command::SetStrength { id, from, to } => Command::SetStrength {
+                id,
+                from: to,
+                to: from,
+            },
+            Command::SetExpr { id, from, to } => Command::SetExpr {
+                id,
+                from: to,
+                to: from,
+            },
+            Command::SetParameter { name, from, to } => Command::SetParameter {
+                name,
+                from: to,
+                to: from,
+            },
+            Command::SetUnknown { name, from, to } => Command::SetUnknown {
+                name,
+                from: to,
+                to: from,
+            },
+        }
+    }
+    fn target(&self) -> Option<Target> {
+        match self {
+            Command::MoveVertex { v_id, .. } => Some(Target::Vertex(*v_id)),
+            Command::SetValue { id, .. } => Some(Target::Value(*id)),
+            Command::SetUnknown { name, .. } => Some(Target::Unknown(name.clone())),
+            _ => None,
+        }
+    }
+    pub fn apply(&self, sketch: &mut Sketch) -> Result<(), String> {
+        match self {
+            Command::AddVertex(v) => sketch.restore_vertex(*v)?,
+            Command::RemoveVertex(v) => {
+                sketch
+                    .remove_vertex(&v.id, false)
+                    .map_err(|e| e.to_string())?;
+            }
+            Command::MoveVertex { v_id, to, .. } => {
+                if !sketch.move_vertex(v_id, *to) {
+                    return Err(format!("unknown vertex {}", **v_id));
+                }
+            }
+            Command::AddShape(shape) => sketch.restore_shape(shape.clone())?,
+            Command::RemoveShape(shape) => {
+                sketch
+                    .remove_shape(&shape.get_id())
+                    .ok_or_else(|| format!("unknown shape {}", *shape.get_id()))?;
+            }
+            Command::AddBinding { bind, expr } => {
+                sketch
+                    .restore_binding(bind.clone())
+                    .map_err(|e| e.to_string())?;
+                if let Some(expr) = expr {
+                    sketch
+                        .set_binding_expr(&bind.get_id(), expr)
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            Command::RemoveBinding { bind, .. } => {
+                sketch
+                    .remove_binding(&bind.get_id())
+                    .ok_or_else(|| format!("unknown binding {}", *bind.get_id()))?;
+            }
+            Command::SetValue { id, to, .. } => match to.as_slice() {
+                [value] if sketch.set_binding_value(id, *value) => (),
+                // The position of a fixed binding is put back with the binding
+                _ => {
+                    let mut bind = sketch
+                        .bindings()
+                        .get(id)
+                        .ok_or_else(|| format!("unknown binding {}", **id))?
+                        .clone();
+                    if !bind.set_values(to) {
+                        return Err(format!("binding {} has no value", **id));
+                    }
+                    sketch.restore_binding(bind).map_err(|e| e.to_string())?;
+                }
+            },
+            Command::SetBounds { v_id, to, .. } => {
+                if !sketch.set_vertex_bounds(v_id, *to) {
+                    return Err(format!("unknown vertex {}", **v_id));
+                }
+            }
+            Command::SetEnabled { id, to, .. } => {
+                if !sketch.set_enabled(id, *to) {
+                    return Err(format!("unknown binding {}", **id));
+                }
+            }
+            Command::SetReference { id, to, .. } => {
+                sketch.set_reference(id, *to).map_err(|e| e.to_string())?
+            }
+            Command::SetStrength { id, to, .. } => {
+                if !sketch.set_strength(id, *to) {
+                    return Err(format!("unknown binding {}", **id));
+                }
+            }
+            Command::SetExpr { id, to, .. } => match to {
+                Some(source) => {
+                    sketch
+                        .set_binding_expr(id, source)
+                        .map_err(|e| e.to_string())?;
+                }
+                None => {
+                    sketch.clear_binding_expr(id);
+                }
+            },
+            Command::SetParameter { name, to, .. } => match to {
+                Some(source) => {
+                    sketch
+                        .set_parameter(name, source)
+                        .map_err(|e| e.to_string())?;
+                }
+                None => sketch.remove_parameter(name).map_err(|e| e.to_string())?,
+            },
+            Command::SetUnknown { name, to, .. } => sketch
+                .set_parameters_solved(&[(name.clone(), *to)])
+                .map_err(|e| e.to_string())?,
+        }
+        Ok(())
+    }
+}
+
+// The commands undone at once
+#[derive(Clone, Debug)]
+struct Step {
+    label: String,
+    commands: Vec<Command>,
+    // The command of each target
+    targets: BTreeMap<Target, usize>,
+}
+impl Step {
+    fn new(label: &str) -> Step {
+        Step {
+            label: label.to_string(),
+            commands: vec![],
+            targets: BTreeMap::new(),
+        }
+    }
+    // A command setting what an earlier command of the step set is merged into
+    // it, so that a drag keeps a move by vertex and not one by mouse event
+    fn push(&mut self, command: Command) {
+        let Some(target) = command.target() else {
+            self.commands.push(command);
+            return;
+        };
+        match self.targets.get(&target) {
+            Some(&i) => match (&mut self.commands[i], command) {
+                (Command::MoveVertex { to, .. }, Command::MoveVertex { to: pt, .. }) => *to = pt,
+                (Command::SetValue { to, .. }, Command::SetValue { to: values, .. }) => {
+                    *to = values
+                }
+                (Command::SetUnknown { to, .. }, Command::SetUnknown { to: value, .. }) => {
+                    *to = value
+                }
+                _ => unreachable!("commands of the same target"),
+            },
+            None => {
+                self.targets.insert(target, self.commands.len());
+                self.commands.push(command);
+            }
+        }
+    }
+}
+
+// A sketch with the history of its edits. The edits that can't be undone go
+// through sketch_mut, which clears the history
+#[derive(Clone, Debug)]
+pub struct History {
+    sketch: Sketch,
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    // The group being recorded, and the depth of the nested groups
+    group: Option<Step>,
+    depth: usize,
+}
+
+impl History {
+    pub fn new(sketch: Sketch) -> History {
+        History {
+            sketch,
+            undo: vec![],
+            redo: vec![],
+            group: None,
+            depth: 0,
+        }
+    }
+    pub fn sketch(&self) -> &Sketch {
+        &self.sketch
+    }
+    pub fn sketch_mut(&mut self) -> &mut Sketch {
+        self.clear();
+        &mut self.sketch
+    }
+    pub fn into_sketch(self) -> Sketch {
+        self.sketch
+    }
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group = None;
+        self.depth = 0;
+    }
+
+    // The edits until the matching end_group are undone as one step, e.g. the
+    // moves and the solves of a drag. The groups nest, the outer label is kept
+    pub fn begin_group(&mut self, label: &str) {
+        if self.depth == 0 {
+            self.group = Some(Step::new(label));
+        }
+        self.depth += 1;
+    }
+    pub fn end_group(&mut self) {
+        match self.depth {
+            0 => (),
+            1 => self.close_group(),
+            _ => self.depth -= 1,
+        }
+    }
+    fn close_group(&mut self) {
+        self.depth = 0;
+        if let Some(step) = self.group.take() {
+            if !step.commands.is_empty() {
+                self.undo.push(step);
+            }
+        }
+    }
+    fn record(&mut self, label: &str, command: Command) {
+        self.redo.clear();
+        match &mut self.group {
+            Some(group) => group.push(command),
+            None => {
+                let mut step = Step::new(label);
+                step.push(command);
+                self.undo.push(step);
+            }
+        }
+    }
+
+    // Labels of the steps undo and redo would take back
+    pub fn undo_label(&self) -> Option<&str> {
+        self.undo.last().map(|step| step.label.as_str())
+    }
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().map(|step| step.label.as_str())
+    }
+    // Undo the last step, an open group being closed first. Return false if there
+    // is nothing to undo. On error the sketch was edited outside of the history,
+    // which is cleared
+    pub fn undo(&mut self) -> Result<bool, String> {
+        self.close_group();
+        let Some(step) = self.undo.pop() else {
+            return Ok(false);
+        };
+        for command in step.commands.iter().rev() {
+            if let Err(e) = command.inverse().apply(&mut self.sketch) {
+                self.clear();
+                return Err(format!("can't undo {}: {e}", step.label));
+            }
+        }
+        self.redo.push(step);
+        Ok(true)
+    }
+    pub fn redo(&mut self) -> Result<bool, String> {
+        self.close_group();
+        let Some(step) = self.redo.pop() else {
+            return Ok(false);
+        };
+        for command in step.commands.iter() {
+            if let Err(e) = command.apply(&mut self.sketch) {
+                self.clear();
+                return Err(format!("can't redo {}: {e}", step.label));
+            }
+        }
+        self.undo.push(step);
+        Ok(true)
+    }
+
+    pub fn add_vertex(&mut self, pt: Point) -> Vertex {
+        let v = self.sketch.add_vertex(pt);
+        self.record("add vertex", Command::AddVertex(v));
+        v
+    }
+    // Remove the vertex, see Sketch::remove_vertex. The bindings and shapes
+    // removed with it are put back by the same undo
+    pub fn remove_vertex(
+        &mut self,
+        v_id: &VertexId,
+        cascade: bool,
+    ) -> Result<RemovedVertex, RemoveError> {
+        let mut exprs: BTreeMap<BindingId, String> = self
+            .sketch
+            .binding_exprs()
+            .map(|(id, source)| (*id, source.to_string()))
+            .collect();
+        let removed = self.sketch.remove_vertex(v_id, cascade)?;
+        self.begin_group("remove vertex");
+        for bind in removed.bindings.iter() {
+            let command = Command::RemoveBinding {
+                bind: bind.clone(),
+                expr: exprs.remove(&bind.get_id()),
+            };
+            self.record("remove vertex", command);
+        }
+        for shape in removed.shapes.iter() {
+            self.record("remove vertex", Command::RemoveShape(shape.clone()));
+        }
+        self.record("remove vertex", Command::RemoveVertex(removed.vertex));
+        self.end_group();
+        Ok(removed)
+    }
+    pub fn move_vertex(&mut self, v_id: &VertexId, pt: Point) -> bool {
+        let Some(from) = self.sketch.vertex(v_id).map(|v| v.pt) else {
+            return false;
+        };
+        self.sketch.move_vertex(v_id, pt);
+        let command = Command::MoveVertex {
+            v_id: *v_id,
+            from,
+            to: pt,
+        };
+        self.record("move vertex", command);
+        true
+    }
+    pub fn set_vertex_bounds(&mut self, v_id: &VertexId, bounds: Option<Rect>) -> bool {
+        let Some(from) = self.sketch.vertex(v_id).map(|v| v.bounds) else {
+            return false;
+        };
+        self.sketch.set_vertex_bounds(v_id, bounds);
+        let command = Command::SetBounds {
+            v_id: *v_id,
+            from,
+            to: bounds,
+        };
+        self.record("edit bounds", command);
+        true
+    }
+    pub fn add_line(&mut self, va: &Vertex, vb: &Vertex) -> LineShape {
+        let line = self.sketch.add_line(va, vb);
+        self.record(
+            "add line",
+            Command::AddShape(ShapeType::STLine(line.clone())),
+        );
+        line
+    }
+    pub fn remove_shape(&mut self, id: &ShapeTypeId) -> Option<ShapeType> {
+        let shape = self.sketch.remove_shape(id)?;
+        self.record("remove shape", Command::RemoveShape(shape.clone()));
+        Some(shape)
+    }
+    // Add a binding with one of the add_bind_* of the sketch, e.g.
+    // history.add_bind(|sketch| sketch.add_bind_distance((&va, &vb)))
+    pub fn add_bind<B>(
+        &mut self,
+        add: impl FnOnce(&mut Sketch) -> Result<B, BindingError>,
+    ) -> Result<B, BindingError> {
+        let last = self.sketch.bindings().keys().next_back().copied();
+        let added = add(&mut self.sketch)?;
+        // The ids only grow, the new binding is the last one
+        if let Some((id, bind)) = self.sketch.bindings().iter().next_back() {
+            if Some(*id) != last {
+                let command = Command::AddBinding {
+                    bind: bind.clone(),
+                    expr: None,
+                };
+                self.record("add binding", command);
+            }
+        }
+        Ok(added)
+    }
+    pub fn add_binding(&mut self, bind: Binding) -> Result<BindingId, BindingError> {
+        self.add_bind(|sketch| sketch.add_binding(bind))
+    }
+    pub fn remove_binding(&mut self, id: &BindingId) -> Option<Binding> {
+        let expr = self.sketch.binding_expr(id).map(str::to_string);
+        let bind = self.sketch.remove_binding(id)?;
+        let command = Command::RemoveBinding {
+            bind: bind.clone(),
+            expr,
+        };
+        self.record("remove binding", command);
+        Some(bind)
+    }
+    // Edit the dimension of a distance or a fixed x or y binding, return false if
+    // the binding has no value
+    pub fn set_value(&mut self, id: &BindingId, value: f64) -> bool {
+        let Some(from) = self.values(id) else {
+            return false;
+        };
+        if !self.sketch.set_binding_value(id, value) {
+            return false;
+        }
+        let command = Command::SetValue {
+            id: *id,
+            from,
+            to: vec![value],
+        };
+        self.record("edit dimension", command);
+        true
+    }
+    fn values(&self, id: &BindingId) -> Option<Vec<f64>> {
+        self.sketch.bindings().get(id).map(Binding::values)
+    }
+    // The values the edit changed, recorded after the edit
+    fn record_values(&mut self, label: &str, id: &BindingId, from: Vec<f64>) {
+        let to = self.values(id).unwrap_or_default();
+        if to != from {
+            let id = *id;
+            self.record(label, Command::SetValue { id, from, to });
+        }
+    }
+    pub fn set_enabled(&mut self, id: &BindingId, enabled: bool) -> bool {
+        let Some(from) = self
+            .sketch
+            .bindings()
+            .get(id)
+            .map(|b| b.get_props().enabled)
+        else {
+            return false;
+        };
+        self.sketch.set_enabled(id, enabled);
+        let command = Command::SetEnabled {
+            id: *id,
+            from,
+            to: enabled,
+        };
+        self.record(if enabled { "enable" } else { "disable" }, command);
+        true
+    }
+    pub fn set_strength(&mut self, id: &BindingId, strength: Strength) -> bool {
+        let Some(from) = self
+            .sketch
+            .bindings()
+            .get(id)
+            .map(|b| b.get_props().strength)
+        else {
+            return false;
+        };
+        self.sketch.set_strength(id, strength);
+        let command = Command::SetStrength {
+            id: *id,
+            from,
+            to: strength,
+        };
+        self.record("edit strength", command);
+        true
+    }
+    // With the value the binding takes, measured or driven
+    pub fn set_reference(&mut self, id: &BindingId, reference: bool) -> Result<(), BindingError> {
+        let bind = self
+            .sketch
+            .bindings()
+            .get(id)
+            .ok_or(BindingError::UnknownBinding(*id))?;
+        let (from, values) = (bind.get_props().reference, bind.values());
+        self.sketch.set_reference(id, reference)?;
+        self.begin_group("toggle reference");
+        let command = Command::SetReference {
+            id: *id,
+            from,
+            to: reference,
+        };
+        self.record("toggle reference", command);
+        self.record_values("toggle reference", id, values);
+        self.end_group();
+        Ok(())
+    }
+    pub fn set_binding_expr(
+        &mut self,
+        id: &BindingId,
+        source: &str,
+    ) -> Result<Vec<f64>, ParamError> {
+        self.set_expr(id, Some(source))
+    }
+    // The binding keeps its current values, return false without expression
+    pub fn clear_binding_expr(&mut self, id: &BindingId) -> bool {
+        self.set_expr(id, None)
+            .is_ok_and(|values| !values.is_empty())
+    }
+    // Return the values of the binding, empty when nothing was cleared
+    fn set_expr(&mut self, id: &BindingId, source: Option<&str>) -> Result<Vec<f64>, ParamError> {
+        let from = self.sketch.binding_expr(id).map(str::to_string);
+        let values = self.values(id).unwrap_or_default();
+        let set = match source {
+            Some(source) => self.sketch.set_binding_expr(id, source)?,
+            None if self.sketch.clear_binding_expr(id) => values.clone(),
+            None => return Ok(vec![]),
+        };
+        self.begin_group("edit expression");
+        let command = Command::SetExpr {
+            id: *id,
+            from,
+            to: source.map(str::to_string),
+        };
+        self.record("edit expression", command);
+        self.record_values("edit expression", id, values);
+        self.end_group();
+        Ok(set)
+    }
+    // Define or redefine a parameter, see Sketch::set_parameter. The driven
+    // values follow the source on undo
+    pub fn set_parameter(&mut self, name: &str, source: &str) -> Result<f64, ParamError> {
+        let from = self
+            .sketch
+            .parameters()
+            .get(name)
+            .map(|param| param.source.clone());
+        let value = self.sketch.set_parameter(name, source)?;
+        let command = Command::SetParameter {
+            name: name.to_string(),
+            from,
+            to: Some(source.to_string()),
+        };
+        self.record("edit parameter", command);
+        Ok(value)
+    }
+
+    // Solve, recording the vertices, the binding values and the unknowns the
+    // solver changed, see Sketch::solve_changes. Recorded even when the solver
+    // did not converge, as the vertices are moved anyway
+    pub fn solve(&mut self, opts: &SolveOptions) -> Result<(), String> {
+        self.record_solve(|sketch| sketch.solve(opts))
+    }
+    pub fn solve_incremental(&mut self, opts: &SolveOptions) -> Result<(), String> {
+        self.record_solve(|sketch| sketch.solve_incremental(opts))
+    }
+    fn record_solve(
+        &mut self,
+        solve: impl FnOnce(&mut Sketch) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let solved = solve(&mut self.sketch);
+        let changes = self.sketch.solve_changes().clone();
+
+        self.begin_group("solve");
+        for (v_id, (from, to)) in changes.vertices {
+            self.record("solve", Command::MoveVertex { v_id, from, to });
+        }
+        for (id, (from, to)) in changes.values {
+            self.record("solve", Command::SetValue { id, from, to });
+        }
+        for (name, (from, to)) in changes.unknowns {
+            self.record("solve", Command::SetUnknown { name, from, to });
+        }
+        self.end_group();
+        solved
+    }
+}
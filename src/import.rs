@@ -0,0 +1,83 @@
+// Import of the drawings of other programs (SVG, DXF), whose shapes become line
+// shapes: the curves are flattened and the endpoints shared
+use std::collections::BTreeSet;
+
+use kurbo::{BezPath, PathEl, Point};
+
+use crate::math::*;
+use crate::sketch::Sketch;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImportOptions {
+    // Accuracy of the flattening of the curves, circles included, into lines
+    pub tolerance: f64,
+    // Endpoints closer than this are merged into one vertex
+    pub merge_distance: f64,
+}
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            tolerance: 0.1,
+            merge_distance: 1e-3,
+        }
+    }
+}
+
+// Builds a sketch from the segments of imported drawings, sharing the endpoints
+pub struct Importer {
+    sketch: Sketch,
+    tolerance: f64,
+    merge_distance: f64,
+    segments: BTreeSet<(VertexId, VertexId)>,
+}
+impl Importer {
+    pub fn new(opts: &ImportOptions) -> Importer {
+        Importer {
+            sketch: Sketch::new(),
+            tolerance: opts.tolerance,
+            merge_distance: opts.merge_distance,
+            segments: BTreeSet::new(),
+        }
+    }
+    pub fn sketch(self) -> Sketch {
+        self.sketch
+    }
+    // The vertex at the point, an existing one when close enough
+    pub fn vertex(&mut self, pt: Point) -> Vertex {
+        let close = self
+            .sketch
+            .vertices()
+            .values()
+            .find(|v| v.pt.distance(pt) <= self.merge_distance);
+        match close {
+            Some(v) => *v,
+            None => self.sketch.add_vertex(pt),
+        }
+    }
+    // A line shape, unless degenerate or already there
+    pub fn segment(&mut self, p0: Point, p1: Point) {
+        if p0.distance(p1) <= self.merge_distance {
+            return;
+        }
+        let (va, vb) = (self.vertex(p0), self.vertex(p1));
+        if va.id != vb.id && self.segments.insert((va.id.min(vb.id), va.id.max(vb.id))) {
+            self.sketch.add_line(&va, &vb);
+        }
+    }
+    pub fn path(&mut self, path: &BezPath) {
+        let (mut start, mut current) = (Point::ZERO, Point::ZERO);
+        path.flatten(self.tolerance, |el| match el {
+            PathEl::MoveTo(pt) => (start, current) = (pt, pt),
+            PathEl::LineTo(pt) => {
+                self.segment(current, pt);
+                current = pt;
+            }
+            PathEl::ClosePath => {
+                self.segment(current, start);
+                current = start;
+            }
+            // Flattening only gives lines
+            _ => (),
+        });
+    }
+}
@@ -0,0 +1,178 @@
+use std::f64::consts::PI;
+
+use crate::bindings::Eq2DConstraints;
+use crate::math::*;
+
+// Tolerances under which a geometric relation is considered intended
+#[derive(Copy, Clone, Debug)]
+pub struct InferenceTolerances {
+    // Angular tolerance in radians for horizontal, vertical, parallel and perpendicular
+    pub angle: f64,
+    // Relative tolerance on the lengths for equal lengths
+    pub length: f64,
+    // Distance under which two endpoints are coincident
+    pub coincidence: f64,
+}
+impl Default for InferenceTolerances {
+    fn default() -> Self {
+        InferenceTolerances {
+            angle: 2f64.to_radians(),
+            length: 0.02,
+            coincidence: 1.,
+        }
+    }
+}
+
+// A suggested binding, the lower the score the closer the geometry already is
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub binding: Binding,
+    pub score: f64,
+}
+
+enum Relation {
+    Horizontal(VertexId, VertexId),
+    Vertical(VertexId, VertexId),
+    Parallel((VertexId, VertexId), (VertexId, VertexId)),
+    Perpendicular((VertexId, VertexId), (VertexId, VertexId)),
+    EqualLength((VertexId, VertexId), (VertexId, VertexId)),
+    Coincident(VertexId, VertexId),
+}
+
+// Suggest bindings for the line shapes, ranked by score.
+// Candidates are accepted one by one on a scratch copy of the pools, a candidate
+// whose equations are dependent on the already accepted ones (checked with the DOF
+// analysis on the projected geometry) is dropped, so adding all the returned
+// bindings to bindings_pool never over-constrains the sketch.
+pub fn infer_bindings(
+    v_pool: &VerticesPool,
+    shapes_pool: &ShapesPool,
+    bindings_pool: &BindingsPool,
+    tol: &InferenceTolerances,
+) -> Result<Vec<Candidate>, String> {
+    let lines: Vec<(VertexId, VertexId)> = shapes_pool
+        .values()
+        .map(|shape| match shape {
+            ShapeType::STLine(line) => (line.va_id, line.vb_id),
+        })
+        .filter(|(va_id, vb_id)| v_pool.contains_key(va_id) && v_pool.contains_key(vb_id))
+        .collect();
+
+    let mut relations = vec![];
+    for (idx, l1) in lines.iter().enumerate() {
+        let a1 = angle(v_pool, l1);
+        // Angle to the horizontal, in [0, PI/2]
+        let dev_h = a1.rem_euclid(PI).min(PI - a1.rem_euclid(PI));
+        if dev_h <= tol.angle {
+            relations.push((dev_h / tol.angle, Relation::Horizontal(l1.0, l1.1)));
+        }
+        let dev_v = (PI / 2. - dev_h).abs();
+        if dev_v <= tol.angle {
+            relations.push((dev_v / tol.angle, Relation::Vertical(l1.0, l1.1)));
+        }
+        for l2 in lines.iter().skip(idx + 1) {
+            let diff = (angle(v_pool, l2) - a1).rem_euclid(PI);
+            let dev_par = diff.min(PI - diff);
+            if dev_par <= tol.angle {
+                relations.push((dev_par / tol.angle, Relation::Parallel(*l1, *l2)));
+            }
+            let dev_perp = (PI / 2. - dev_par).abs();
+            if dev_perp <= tol.angle {
+                relations.push((dev_perp / tol.angle, Relation::Perpendicular(*l1, *l2)));
+            }
+            let (len1, len2) = (length(v_pool, l1), length(v_pool, l2));
+            let dev_len = (len1 - len2).abs() / len1.max(len2);
+            if dev_len <= tol.length {
+                relations.push((dev_len / tol.length, Relation::EqualLength(*l1, *l2)));
+            }
+        }
+    }
+
+    let mut endpoints: Vec<VertexId> = lines.iter().flat_map(|(va, vb)| [*va, *vb]).collect();
+    endpoints.sort_by_key(|v_id| **v_id);
+    endpoints.dedup();
+    for (idx, va_id) in endpoints.iter().enumerate() {
+        for vb_id in endpoints.iter().skip(idx + 1) {
+            let dist = v_pool[va_id].pt.distance(v_pool[vb_id].pt);
+            if dist <= tol.coincidence {
+                relations.push((dist / tol.coincidence, Relation::Coincident(*va_id, *vb_id)));
+            }
+        }
+    }
+
+    relations.sort_by(|(s1, _), (s2, _)| s1.total_cmp(s2));
+
+    let mut scratch_v_pool = v_pool.clone();
+    let mut scratch_pool = bindings_pool.clone();
+    let mut candidates = vec![];
+    for (score, relation) in relations {
+        // Relations already bound are duplicates
+        let Ok(binding) = add_relation(&mut scratch_pool, &scratch_v_pool, &relation) else {
+            continue;
+        };
+        let id = binding.get_id();
+        let projected = {
+            let cst = Eq2DConstraints::new(&scratch_pool, &scratch_v_pool)?;
+            let mut x = cst.initial();
+            if cst.project(&mut x, 1e-9, 50) && !cst.dof_analysis(&x).is_over_constrained() {
+                Some(cst.solution(&x))
+            } else {
+                None
+            }
+        };
+        match projected {
+            Some(solution) => {
+                // Following candidates are checked against the snapped geometry
+                solution.iter().for_each(|(v_id, pt)| {
+                    scratch_v_pool.set_pt(v_id, *pt);
+                });
+                candidates.push(Candidate { binding, score });
+            }
+            None => _ = scratch_pool.remove_binding(&id),
+        }
+    }
+    Ok(candidates)
+}
+
+fn add_relation(
+    pool: &mut BindingsPool,
+    v_pool: &VerticesPool,
+    relation: &Relation,
+) -> Result<Binding, BindingError> {
+    let seg = |l: &(VertexId, VertexId)| (v_pool[&l.0], v_pool[&l.1]);
+    match relation {
+        Relation::Horizontal(va, vb) => pool
+            .add_bind_horizontal((&v_pool[va], &v_pool[vb]))
+            .map(Binding::Horizontal),
+        Relation::Vertical(va, vb) => pool
+            .add_bind_vertical((&v_pool[va], &v_pool[vb]))
+            .map(Binding::Vertical),
+        Relation::Parallel(l1, l2) => {
+            let (s1, s2) = (seg(l1), seg(l2));
+            pool.add_bind_parallel((&s1.0, &s1.1), (&s2.0, &s2.1))
+                .map(Binding::Parallel)
+        }
+        Relation::Perpendicular(l1, l2) => {
+            let (s1, s2) = (seg(l1), seg(l2));
+            pool.add_bind_perpendicular((&s1.0, &s1.1), (&s2.0, &s2.1))
+                .map(Binding::Perpendicular)
+        }
+        Relation::EqualLength(l1, l2) => {
+            let (s1, s2) = (seg(l1), seg(l2));
+            pool.add_bind_equal_length((&s1.0, &s1.1), (&s2.0, &s2.1))
+                .map(Binding::EqualLength)
+        }
+        Relation::Coincident(va, vb) => pool
+            .add_bind_coincident(&v_pool[va], &v_pool[vb])
+            .map(Binding::Coincident),
+    }
+}
+
+fn angle(v_pool: &VerticesPool, l: &(VertexId, VertexId)) -> f64 {
+    let d = v_pool[&l.1].pt - v_pool[&l.0].pt;
+    d.y.atan2(d.x)
+}
+
+fn length(v_pool: &VerticesPool, l: &(VertexId, VertexId)) -> f64 {
+    v_pool[&l.0].pt.distance(v_pool[&l.1].pt)
+}
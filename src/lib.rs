@@ -0,0 +1,15 @@
+// Geometric constraint solving of 2D sketches: vertices, line shapes and the
+// bindings between them, solved with gomez. Programs can define their own
+// bindings by implementing math::Constraint, see Sketch::add_bind_custom
+pub mod bindings;
+pub mod branches;
+pub mod document;
+pub mod dsl;
+pub mod dxf;
+pub mod history;
+pub mod import;
+pub mod inference;
+pub mod math;
+pub mod params;
+pub mod sketch;
+pub mod svg;
@@ -1,52 +1,251 @@
-use kurbo::Point;
-mod bindings;
-mod math;
-use bindings::Eq2DConstraints;
-use math::*;
-
-fn main() -> Result<(), String> {
-    let mut bind_pool = BindingsPool::new();
-    let mut v_pool = VerticesPool::new();
-
-    let va = v_pool.add(Point::new(-27., 30.));
-    let vb = v_pool.add(Point::new(120., 20.));
-    let vc = v_pool.add(Point::new(-10., 60.));
-    let vd = v_pool.add(Point::new(0., 52.));
-
-    println!("va: ({:.2},{:.2}) ", va.pt.x, va.pt.y);
-    println!("vb: ({:.2},{:.2})", vb.pt.x, vb.pt.y);
-    println!("vc: ({:.2},{:.2}) ", vc.pt.x, vc.pt.y);
-    println!("vd: ({:.2},{:.2})", vd.pt.x, vd.pt.y);
-
-    println!("m1: {:.4} ", (vb.pt.y - va.pt.y) / (vb.pt.x - va.pt.x));
-    println!("m2: {:.4} ", (vd.pt.y - vc.pt.y) / (vd.pt.x - vc.pt.x));
-    println!("dist(va,vd): {:.4} ", va.dist_sq(&vd));
-
-    // 8 DOF and 8 Eq => Determined system
-    // Undertermined system sometimes work (converge) sometimes no
-    _ = bind_pool.add_bind_parallel((&va, &vb), (&vc, &vd)); // 1 eq
-    _ = bind_pool.add_bind_vertical((&vc, &vd)); // 1 eq
-    _ = bind_pool.add_bind_fixed(&va); // 2 eq
-    _ = bind_pool.add_bind_fixed(&vc); // 2 eq
-    _ = bind_pool.add_bind_fixed_y(&vb); // 1 eq
-    _ = bind_pool.add_bind_distance((&va, &vd)); // 1 eq
-
-    let mut cst = Eq2DConstraints::new(&mut bind_pool, &mut v_pool);
-    cst.solve(&mut v_pool)?;
-
-    let va = v_pool.get(&va.id).unwrap();
-    let vb = v_pool.get(&vb.id).unwrap();
-    let vc = v_pool.get(&vc.id).unwrap();
-    let vd = v_pool.get(&vd.id).unwrap();
-
-    println!("va: ({:.2},{:.2}) ", va.pt.x, va.pt.y);
-    println!("vb: ({:.2},{:.2})", vb.pt.x, vb.pt.y);
-    println!("vc: ({:.2},{:.2}) ", vc.pt.x, vc.pt.y);
-    println!("vd: ({:.2},{:.2})", vd.pt.x, vd.pt.y);
-
-    println!("m1: {:.4} ", (vb.pt.y - va.pt.y) / (vb.pt.x - va.pt.x));
-    println!("m2: {:.4} ", (vd.pt.y - vc.pt.y) / (vd.pt.x - vc.pt.x));
-    println!("dist(va,vd): {:.4} ", va.dist_sq(&vd));
-
-    Ok(())
+use std::fs;
+use std::process::ExitCode;
+
+use serde_json::json;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::import::ImportOptions;
+use test_gomez::sketch::Sketch;
+use test_gomez::{document, dsl, dxf, svg};
+
+const USAGE: &str = "usage:
+    test-gomez solve <sketch> [--tol <tol>] [--max-iter <n>] [--format text|json|svg|dxf]
+                              [--output <sketch>] [--preserve-orientation] [--verbose]
+    test-gomez check <sketch> [--format text|json]
+    test-gomez convert <sketch> <output> [--format text|json|svg|dxf]
+
+Sketches are read and written as JSON documents for the .json files, in the text
+format otherwise. The .svg and .dxf files are drawings, their shapes are read as
+lines and the dimensions of the .dxf files as bindings. The output '-' of convert
+is the standard output, in the --format format.
+solve and check print a report in the --format format, text or json, the solved
+sketch is written to a file with --output. The --verbose traces go to the
+standard error.
+solve exits with 1 when the solver doesn't converge, 2 on errors.";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Svg,
+    Dxf,
+}
+impl Format {
+    fn of_path(path: &str) -> Format {
+        if path.ends_with(".json") {
+            Format::Json
+        } else if path.ends_with(".svg") {
+            Format::Svg
+        } else if path.ends_with(".dxf") {
+            Format::Dxf
+        } else {
+            Format::Text
+        }
+    }
+}
+
+struct Args {
+    command: String,
+    paths: Vec<String>,
+    opts: SolveOptions,
+    format: Option<Format>,
+    output: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut args = args.iter();
+    let command = args.next().ok_or("missing command")?.clone();
+    let mut parsed = Args {
+        command,
+        paths: vec![],
+        opts: SolveOptions::default(),
+        format: None,
+        output: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("missing value of {name}"))
+        };
+        match arg.as_str() {
+            "--tol" => {
+                let tol = value(arg)?;
+                parsed.opts.tolerance = tol.parse().map_err(|_| format!("invalid --tol {tol}"))?;
+            }
+            "--max-iter" => {
+                let max_iter = value(arg)?;
+                parsed.opts.max_iter = max_iter
+                    .parse()
+                    .map_err(|_| format!("invalid --max-iter {max_iter}"))?;
+            }
+            "--format" => {
+                parsed.format = match value(arg)?.as_str() {
+                    "text" => Some(Format::Text),
+                    "json" => Some(Format::Json),
+                    "svg" => Some(Format::Svg),
+                    "dxf" => Some(Format::Dxf),
+                    format => return Err(format!("unknown format {format}")),
+                }
+            }
+            "--output" => parsed.output = Some(value(arg)?),
+            "--preserve-orientation" => parsed.opts.preserve_orientation = true,
+            "--verbose" => parsed.opts.verbose = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => parsed.paths.push(arg.clone()),
+        }
+    }
+    Ok(parsed)
+}
+
+fn read_sketch(path: &str) -> Result<Sketch, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    match Format::of_path(path) {
+        Format::Json => document::from_json(&text).map_err(|e| e.to_string()),
+        Format::Text => dsl::parse(&text).map_err(|e| e.to_string()),
+        Format::Svg => svg::import(&text, &ImportOptions::default()),
+        Format::Dxf => dxf::import(&text, &ImportOptions::default()),
+    }
+    .map_err(|e| format!("{path}: {e}"))
+}
+
+fn write_sketch(sketch: &Sketch, path: &str, format: Option<Format>) -> Result<(), String> {
+    let format = match path {
+        "-" => format.unwrap_or(Format::Text),
+        _ => Format::of_path(path),
+    };
+    let text = match format {
+        Format::Json => document::to_json(sketch).map_err(|e| e.to_string())?,
+        Format::Text => dsl::print(sketch).map_err(|e| e.to_string())?,
+        Format::Svg => svg::export(sketch, &svg::SvgOptions::default()),
+        Format::Dxf => dxf::export(sketch),
+    };
+    match path {
+        "-" => {
+            print!("{text}");
+            Ok(())
+        }
+        _ => fs::write(path, text).map_err(|e| format!("{path}: {e}")),
+    }
+}
+
+fn single_path(args: &Args) -> Result<&str, String> {
+    match args.paths.as_slice() {
+        [path] => Ok(path),
+        _ => Err(format!("{} takes one sketch", args.command)),
+    }
+}
+
+// The reports of solve and check are text or JSON
+fn report_format(args: &Args) -> Result<Format, String> {
+    match args.format.unwrap_or(Format::Text) {
+        Format::Svg | Format::Dxf => Err(format!("{} reports are text or json", args.command)),
+        format => Ok(format),
+    }
+}
+
+fn run(args: &Args) -> Result<ExitCode, String> {
+    match args.command.as_str() {
+        "solve" => {
+            let format = report_format(args)?;
+            // The report takes the standard output
+            if args.output.as_deref() == Some("-") {
+                return Err("solve prints its report, the output must be a file".to_string());
+            }
+            let mut sketch = read_sketch(single_path(args)?)?;
+            let solved = sketch.solve(&args.opts);
+            let dof = sketch.dof_analysis()?;
+            match format {
+                Format::Json => {
+                    let report = json!({
+                        "converged": solved.is_ok(),
+                        "error": solved.as_ref().err(),
+                        "dof": dof.dof(),
+                        "redundant": dof.redundant(),
+                        "vertices": sketch
+                            .vertices()
+                            .values()
+                            .map(|v| json!({ "id": *v.id, "x": v.pt.x, "y": v.pt.y }))
+                            .collect::<Vec<_>>(),
+                        "parameters": sketch.parameters().values(),
+                    });
+                    println!("{report:#}");
+                }
+                _ => {
+                    match &solved {
+                        Ok(()) => println!("converged"),
+                        Err(e) => println!("failed: {e}"),
+                    }
+                    println!("dof: {}, redundant: {}", dof.dof(), dof.redundant());
+                    sketch
+                        .vertices()
+                        .values()
+                        .for_each(|v| println!("p{} = ({}, {})", *v.id, v.pt.x, v.pt.y));
+                    sketch
+                        .parameters()
+                        .values()
+                        .iter()
+                        .for_each(|(name, value)| println!("{name} = {value}"));
+                }
+            }
+            if let Some(output) = &args.output {
+                write_sketch(&sketch, output, args.format)?;
+            }
+            Ok(match solved {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(_) => ExitCode::from(1),
+            })
+        }
+        "check" => {
+            let format = report_format(args)?;
+            let sketch = read_sketch(single_path(args)?)?;
+            let dof = sketch.dof_analysis()?;
+            match format {
+                Format::Json => {
+                    let report = json!({
+                        "variables": dof.n_vars,
+                        "equations": dof.n_eqs,
+                        "rank": dof.rank,
+                        "dof": dof.dof(),
+                        "redundant": dof.redundant(),
+                    });
+                    println!("{report:#}");
+                }
+                _ => {
+                    println!(
+                        "variables: {}, equations: {}, rank: {}",
+                        dof.n_vars, dof.n_eqs, dof.rank
+                    );
+                    println!("dof: {}, redundant: {}", dof.dof(), dof.redundant());
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        "convert" => match args.paths.as_slice() {
+            [input, output] => {
+                write_sketch(&read_sketch(input)?, output, args.format)?;
+                Ok(ExitCode::SUCCESS)
+            }
+            _ => Err("convert takes a sketch and an output".to_string()),
+        },
+        command => Err(format!("unknown command {command}, see --help")),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "--help" || args[0] == "help" {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    run(&args).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        ExitCode::from(2)
+    })
 }
@@ -1,12 +1,13 @@
-use kurbo::Point;
+use kurbo::{BezPath, Line, Point, Rect, Shape};
+use serde::{ser, Deserialize, Serialize, Serializer};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet},
+    fmt,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
 };
 
-#[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Binding {
     Fixed(BindFixed),
     FixedX(BindFixedX),
@@ -15,9 +16,16 @@ pub enum Binding {
     Horizontal(BindHorizontal),
     Parallel(BindParallel),
     Distance(BindDistance),
-    Error(BindError),
+    Perpendicular(BindPerpendicular),
+    EqualLength(BindEqualLength),
+    Coincident(BindCoincident),
+    DistanceRange(BindDistanceRange),
+    HalfPlane(BindHalfPlane),
+    AngleRange(BindAngleRange),
+    // Only known to the program that added it, saving it is an error
+    #[serde(serialize_with = "BindCustom::serialize", skip_deserializing)]
+    Custom(BindCustom),
 }
-#[allow(dead_code)]
 impl Binding {
     pub fn get_id(&self) -> BindingId {
         match self {
@@ -28,60 +36,330 @@ impl Binding {
             Binding::Horizontal(b) => b.id,
             Binding::Parallel(b) => b.id,
             Binding::Distance(b) => b.id,
-            Binding::Error(b) => b.id,
+            Binding::Perpendicular(b) => b.id,
+            Binding::EqualLength(b) => b.id,
+            Binding::Coincident(b) => b.id,
+            Binding::DistanceRange(b) => b.id,
+            Binding::HalfPlane(b) => b.id,
+            Binding::AngleRange(b) => b.id,
+            Binding::Custom(b) => b.id,
         }
     }
-    pub fn get_v_ids(&self, v_ids: &mut HashSet<VertexId>) {
+    pub fn set_id(&mut self, id: BindingId) {
         match self {
-            Binding::Fixed(b) => {
-                v_ids.insert(b.v_id);
-            }
-            Binding::FixedX(b) => {
-                v_ids.insert(b.v_id);
-            }
-            Binding::FixedY(b) => {
-                v_ids.insert(b.v_id);
-            }
-            Binding::Vertical(b) => {
-                v_ids.insert(b.va_id);
-                v_ids.insert(b.vb_id);
-            }
-            Binding::Horizontal(b) => {
-                v_ids.insert(b.va_id);
-                v_ids.insert(b.vb_id);
-            }
-            Binding::Parallel(b) => {
-                v_ids.insert(b.l1va_id);
-                v_ids.insert(b.l1vb_id);
-                v_ids.insert(b.l2va_id);
-                v_ids.insert(b.l2vb_id);
-            }
+            Binding::Fixed(b) => b.id = id,
+            Binding::FixedX(b) => b.id = id,
+            Binding::FixedY(b) => b.id = id,
+            Binding::Vertical(b) => b.id = id,
+            Binding::Horizontal(b) => b.id = id,
+            Binding::Parallel(b) => b.id = id,
+            Binding::Distance(b) => b.id = id,
+            Binding::Perpendicular(b) => b.id = id,
+            Binding::EqualLength(b) => b.id = id,
+            Binding::Coincident(b) => b.id = id,
+            Binding::DistanceRange(b) => b.id = id,
+            Binding::HalfPlane(b) => b.id = id,
+            Binding::AngleRange(b) => b.id = id,
+            Binding::Custom(b) => b.id = id,
+        }
+    }
+    pub fn get_props(&self) -> &BindProps {
+        match self {
+            Binding::Fixed(b) => &b.props,
+            Binding::FixedX(b) => &b.props,
+            Binding::FixedY(b) => &b.props,
+            Binding::Vertical(b) => &b.props,
+            Binding::Horizontal(b) => &b.props,
+            Binding::Parallel(b) => &b.props,
+            Binding::Distance(b) => &b.props,
+            Binding::Perpendicular(b) => &b.props,
+            Binding::EqualLength(b) => &b.props,
+            Binding::Coincident(b) => &b.props,
+            Binding::DistanceRange(b) => &b.props,
+            Binding::HalfPlane(b) => &b.props,
+            Binding::AngleRange(b) => &b.props,
+            Binding::Custom(b) => &b.props,
+        }
+    }
+    pub fn props_mut(&mut self) -> &mut BindProps {
+        match self {
+            Binding::Fixed(b) => &mut b.props,
+            Binding::FixedX(b) => &mut b.props,
+            Binding::FixedY(b) => &mut b.props,
+            Binding::Vertical(b) => &mut b.props,
+            Binding::Horizontal(b) => &mut b.props,
+            Binding::Parallel(b) => &mut b.props,
+            Binding::Distance(b) => &mut b.props,
+            Binding::Perpendicular(b) => &mut b.props,
+            Binding::EqualLength(b) => &mut b.props,
+            Binding::Coincident(b) => &mut b.props,
+            Binding::DistanceRange(b) => &mut b.props,
+            Binding::HalfPlane(b) => &mut b.props,
+            Binding::AngleRange(b) => &mut b.props,
+            Binding::Custom(b) => &mut b.props,
+        }
+    }
+    // The binding as seen by the solver
+    pub fn constraint(&self) -> &dyn Constraint {
+        match self {
+            Binding::Fixed(b) => b,
+            Binding::FixedX(b) => b,
+            Binding::FixedY(b) => b,
+            Binding::Vertical(b) => b,
+            Binding::Horizontal(b) => b,
+            Binding::Parallel(b) => b,
+            Binding::Distance(b) => b,
+            Binding::Perpendicular(b) => b,
+            Binding::EqualLength(b) => b,
+            Binding::Coincident(b) => b,
+            Binding::DistanceRange(b) => b,
+            Binding::HalfPlane(b) => b,
+            Binding::AngleRange(b) => b,
+            Binding::Custom(b) => b,
+        }
+    }
+    // Number of equations the binding adds to the system
+    pub fn eq_count(&self) -> usize {
+        self.constraint().eq_count()
+    }
+    // The pairs of vertices of the binding: its segments, the line of a half
+    // plane, the coincident vertices
+    pub fn segments(&self) -> Vec<(VertexId, VertexId)> {
+        match self {
+            Binding::Fixed(_) | Binding::FixedX(_) | Binding::FixedY(_) => vec![],
+            Binding::Vertical(b) => vec![(b.va_id, b.vb_id)],
+            Binding::Horizontal(b) => vec![(b.va_id, b.vb_id)],
+            Binding::Distance(b) => vec![(b.va_id, b.vb_id)],
+            Binding::Coincident(b) => vec![(b.va_id, b.vb_id)],
+            Binding::DistanceRange(b) => vec![(b.va_id, b.vb_id)],
+            Binding::HalfPlane(b) => vec![(b.la_id, b.lb_id)],
+            Binding::Parallel(b) => vec![(b.l1va_id, b.l1vb_id), (b.l2va_id, b.l2vb_id)],
+            Binding::Perpendicular(b) => vec![(b.l1va_id, b.l1vb_id), (b.l2va_id, b.l2vb_id)],
+            Binding::EqualLength(b) => vec![(b.l1va_id, b.l1vb_id), (b.l2va_id, b.l2vb_id)],
+            Binding::AngleRange(b) => vec![(b.l1va_id, b.l1vb_id), (b.l2va_id, b.l2vb_id)],
+            Binding::Custom(_) => vec![],
+        }
+    }
+    // Kind of the binding with its vertices, in an order independent of the order
+    // of the segments and of their ends. None for the custom bindings, which can't
+    // be compared
+    fn signature(&self) -> Option<(std::mem::Discriminant<Binding>, Vec<VertexId>)> {
+        if let Binding::Custom(_) = self {
+            return None;
+        }
+        let mut segments: Vec<(VertexId, VertexId)> = self
+            .segments()
+            .into_iter()
+            .map(|(va_id, vb_id)| (va_id.min(vb_id), va_id.max(vb_id)))
+            .collect();
+        segments.sort();
+        let mut v_ids: Vec<VertexId> = match self {
+            Binding::Fixed(b) => vec![b.v_id],
+            Binding::FixedX(b) => vec![b.v_id],
+            Binding::FixedY(b) => vec![b.v_id],
+            Binding::HalfPlane(b) => vec![b.v_id],
+            _ => vec![],
+        };
+        v_ids.extend(
+            segments
+                .into_iter()
+                .flat_map(|(va_id, vb_id)| [va_id, vb_id]),
+        );
+        Some((std::mem::discriminant(self), v_ids))
+    }
+    pub fn get_v_ids(&self, v_ids: &mut BTreeSet<VertexId>) {
+        v_ids.extend(self.constraint().v_ids());
+    }
+    // Bounds of the inequality bindings: the solver adds a slack variable s with
+    // lower <= s <= upper and the equation value(x) - s = 0
+    pub fn bounds(&self) -> Option<(f64, f64)> {
+        self.constraint().bounds()
+    }
+    // The dimension of the bindings that have one, a distance or a coordinate
+    pub fn value(&self) -> Option<f64> {
+        match self {
+            Binding::FixedX(b) => Some(b.fixed_value),
+            Binding::FixedY(b) => Some(b.fixed_value),
+            Binding::Distance(b) => Some(b.sq_distance_value.sqrt()),
+            _ => None,
+        }
+    }
+    // The dimension as measured on the vertices
+    pub fn measure(&self, v_pool: &VerticesPool) -> Option<f64> {
+        match self {
+            Binding::FixedX(b) => Some(v_pool.get(&b.v_id)?.pt.x),
+            Binding::FixedY(b) => Some(v_pool.get(&b.v_id)?.pt.y),
             Binding::Distance(b) => {
-                v_ids.insert(b.va_id);
-                v_ids.insert(b.vb_id);
+                Some(v_pool.get(&b.va_id)?.pt.distance(v_pool.get(&b.vb_id)?.pt))
             }
-            Binding::Error(_) => (),
-        };
+            _ => None,
+        }
+    }
+    // Return false if the binding has no dimension
+    pub fn set_value(&mut self, value: f64) -> bool {
+        match self {
+            Binding::FixedX(b) => b.fixed_value = value,
+            Binding::FixedY(b) => b.fixed_value = value,
+            Binding::Distance(b) => b.sq_distance_value = value.powi(2),
+            _ => return false,
+        }
+        true
+    }
+    // Whether the binding can be a reference, its dimension being measured
+    pub fn is_measurable(&self) -> bool {
+        self.value().is_some()
+    }
+    // All the dimensions, the coordinates of a fixed vertex or the only one of
+    // value, empty without dimension
+    pub fn values(&self) -> Vec<f64> {
+        match self {
+            Binding::Fixed(b) => vec![b.fixed_value.x, b.fixed_value.y],
+            _ => self.value().into_iter().collect(),
+        }
+    }
+    // Return false if the binding doesn't have as many dimensions
+    pub fn set_values(&mut self, values: &[f64]) -> bool {
+        match (self, values) {
+            (Binding::Fixed(b), [x, y]) => b.fixed_value = Point::new(*x, *y),
+            (Binding::Fixed(_), _) => return false,
+            (bind, [value]) => return bind.set_value(*value),
+            _ => return false,
+        }
+        true
+    }
+    pub fn uses_vertex(&self, v_id: &VertexId) -> bool {
+        let mut v_ids = BTreeSet::new();
+        self.get_v_ids(&mut v_ids);
+        v_ids.contains(v_id)
     }
 }
 
-#[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
-pub struct BindError {
-    pub id: BindingId,
+// What the solver needs from a binding. The values the residuals and the
+// jacobian are given are the x and y of each vertex of v_ids, in that order.
+// Implement it to solve custom bindings, see BindingsPool::add_bind_custom
+pub trait Constraint: fmt::Debug {
+    fn v_ids(&self) -> Vec<VertexId>;
+    // Number of equations, and of residuals written
+    fn eq_count(&self) -> usize;
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]);
+    // Derivatives of the residuals by the values, row by row: jac holds
+    // eq_count * vals.len() values. Central differences unless overridden
+    fn jacobian(&self, vals: &[f64], jac: &mut [f64]) {
+        let n_eqs = self.eq_count();
+        let mut vh = vals.to_vec();
+        let mut r_plus = vec![0.; n_eqs];
+        let mut r_minus = vec![0.; n_eqs];
+        for col in 0..vals.len() {
+            let h = 1e-6 * vals[col].abs().max(1.);
+            vh[col] = vals[col] + h;
+            self.residuals(&vh, &mut r_plus);
+            vh[col] = vals[col] - h;
+            self.residuals(&vh, &mut r_minus);
+            vh[col] = vals[col];
+            for row in 0..n_eqs {
+                jac[row * vals.len() + col] = (r_plus[row] - r_minus[row]) / (2. * h);
+            }
+        }
+    }
+    // Bounds of an inequality of one equation: the solver adds a slack variable s
+    // with lower <= s <= upper and the equation value(x) - s = 0
+    fn bounds(&self) -> Option<(f64, f64)> {
+        None
+    }
 }
-#[allow(dead_code)]
-impl BindError {
-    pub fn new() -> Binding {
-        return Binding::Error(BindError {
-            id: BindingId::new_id(),
-        });
+
+// Properties shared by all the bindings
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindProps {
+    pub strength: Strength,
+    // A reference (driven) binding adds no equation, its value is measured on
+    // the geometry after each solve, see Binding::is_measurable
+    pub reference: bool,
+    // A disabled (suppressed) binding stays in the pool but adds no equation
+    pub enabled: bool,
+}
+impl Default for BindProps {
+    fn default() -> Self {
+        BindProps {
+            strength: Strength::default(),
+            reference: false,
+            enabled: true,
+        }
+    }
+}
+impl BindProps {
+    // Whether the binding adds equations to the system
+    pub fn is_driving(&self) -> bool {
+        self.enabled && !self.reference
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+// How hard a binding is enforced. Required bindings are equations the solution
+// satisfies exactly, the others are preferences whose weighted squared violation
+// is minimised, Cassowary style.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Strength {
+    #[default]
+    Required,
+    Strong,
+    Medium,
+    Weak,
+    Weight(f64),
+}
+impl Strength {
+    // Weight of the squared residuals, None for a required binding
+    pub fn weight(&self) -> Option<f64> {
+        match self {
+            Strength::Required => None,
+            Strength::Strong => Some(1e3),
+            Strength::Medium => Some(1.),
+            Strength::Weak => Some(1e-3),
+            Strength::Weight(w) => Some(*w),
+        }
+    }
+}
+
+// Why a binding can't be added
+#[derive(Clone, Debug, PartialEq)]
+pub enum BindingError {
+    UnknownVertex(VertexId),
+    // A segment from the vertex to itself
+    IdenticalEndpoints(VertexId),
+    // The existing binding of the same kind on the same vertices
+    Duplicate(BindingId),
+    // A segment of null length
+    DegenerateParallel,
+    // Two segments of the binding are the same one, e.g. a segment perpendicular
+    // to itself
+    SameSegment(VertexId, VertexId),
+    UnknownBinding(BindingId),
+    // Only the bindings with a dimension can be references
+    NotMeasurable(BindingId),
+}
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingError::UnknownVertex(v_id) => write!(f, "unknown vertex {v_id:?}"),
+            BindingError::IdenticalEndpoints(v_id) => {
+                write!(f, "segment from vertex {v_id:?} to itself")
+            }
+            BindingError::Duplicate(id) => write!(f, "duplicate of binding {id:?}"),
+            BindingError::DegenerateParallel => write!(f, "degenerate parallel segments"),
+            BindingError::SameSegment(va_id, vb_id) => {
+                write!(f, "segment ({va_id:?}, {vb_id:?}) bound to itself")
+            }
+            BindingError::UnknownBinding(id) => write!(f, "unknown binding {id:?}"),
+            BindingError::NotMeasurable(id) => {
+                write!(f, "binding {id:?} has no dimension to measure")
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindFixed {
     pub id: BindingId,
+    pub props: BindProps,
     pub fixed_value: Point,
     pub v_id: VertexId,
 }
@@ -90,10 +368,26 @@ impl BindFixed {
         [vals[0] - self.fixed_value.x, vals[1] - self.fixed_value.y]
     }
 }
+impl Constraint for BindFixed {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.v_id]
+    }
+    fn eq_count(&self) -> usize {
+        2
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        let bind = self.bind(vals.try_into().unwrap());
+        rx.copy_from_slice(&bind);
+    }
+    fn jacobian(&self, _vals: &[f64], jac: &mut [f64]) {
+        jac.copy_from_slice(&[1., 0., 0., 1.]);
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindFixedX {
     pub id: BindingId,
+    pub props: BindProps,
     pub fixed_value: f64,
     pub v_id: VertexId,
 }
@@ -102,10 +396,25 @@ impl BindFixedX {
         vals[0] - self.fixed_value
     }
 }
+impl Constraint for BindFixedX {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.v_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn jacobian(&self, _vals: &[f64], jac: &mut [f64]) {
+        jac.copy_from_slice(&[1., 0.]);
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindFixedY {
     pub id: BindingId,
+    pub props: BindProps,
     pub fixed_value: f64,
     pub v_id: VertexId,
 }
@@ -114,10 +423,25 @@ impl BindFixedY {
         vals[1] - self.fixed_value
     }
 }
+impl Constraint for BindFixedY {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.v_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn jacobian(&self, _vals: &[f64], jac: &mut [f64]) {
+        jac.copy_from_slice(&[0., 1.]);
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindVertical {
     pub id: BindingId,
+    pub props: BindProps,
     pub va_id: VertexId,
     pub vb_id: VertexId,
 }
@@ -126,10 +450,25 @@ impl BindVertical {
         vals[0] - vals[2]
     }
 }
+impl Constraint for BindVertical {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.va_id, self.vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn jacobian(&self, _vals: &[f64], jac: &mut [f64]) {
+        jac.copy_from_slice(&[1., 0., -1., 0.]);
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindHorizontal {
     pub id: BindingId,
+    pub props: BindProps,
     pub va_id: VertexId,
     pub vb_id: VertexId,
 }
@@ -138,10 +477,25 @@ impl BindHorizontal {
         vals[1] - vals[3]
     }
 }
+impl Constraint for BindHorizontal {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.va_id, self.vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn jacobian(&self, _vals: &[f64], jac: &mut [f64]) {
+        jac.copy_from_slice(&[0., 1., 0., -1.]);
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindParallel {
     pub id: BindingId,
+    pub props: BindProps,
     pub l1va_id: VertexId,
     pub l1vb_id: VertexId,
     pub l2va_id: VertexId,
@@ -152,245 +506,325 @@ impl BindParallel {
         (vals[6] - vals[4]) * (vals[3] - vals[1]) - (vals[7] - vals[5]) * (vals[2] - vals[0])
     }
 }
+impl Constraint for BindParallel {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.l1va_id, self.l1vb_id, self.l2va_id, self.l2vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BindDistance {
     pub id: BindingId,
+    pub props: BindProps,
     pub sq_distance_value: f64,
     pub va_id: VertexId,
     pub vb_id: VertexId,
 }
 impl BindDistance {
+    // Signed: the absolute value has no derivative at the solution, where the
+    // Newton steps and the rank of the DOF analysis need one
     pub fn bind(&self, vals: &[f64; 4]) -> f64 {
-        ((vals[3] - vals[1]).powi(2) + ((vals[2] - vals[0]).powi(2)) - self.sq_distance_value).abs()
-    }
-}
-
-// pub trait ApiShapes {
-//     fn get_id(&self) -> ShapeTypeId;
-//     fn is_selected(&self) -> bool;
-//     fn set_selected(&mut self, selection: bool);
-//     fn set_all_vertices(&mut self, selection: bool, vertices_pool: &mut HashMap<VertexId, Vertex>);
-//     fn get_vertex_selected(&self, vertices_pool: &HashMap<VertexId, Vertex>) -> Option<VertexId>;
-//     fn get_vertex_under_pos(
-//         &self,
-//         pt: &Point,
-//         grab_handle_precision: f64,
-//         vertices_pool: &HashMap<VertexId, Vertex>,
-//     ) -> Option<VertexId>;
-//     fn select_vertex_under_pos(
-//         &mut self,
-//         pt: &Point,
-//         grab_handle_precision: f64,
-//         vertices_pool: &mut HashMap<VertexId, Vertex>,
-//     );
-//     fn move_selection(&mut self, dpt: &Point, vertices_pool: &mut HashMap<VertexId, Vertex>);
-//     fn move_selection_end(&mut self, vertices_pool: &mut HashMap<VertexId, Vertex>);
-//     fn get_handles_vertices(&self, vertices_pool: &HashMap<VertexId, Vertex>) -> Vec<Vertex>;
-//     fn get_path(&self, tol: f64, vertices_pool: &HashMap<VertexId, Vertex>) -> BezPath;
-// }
-// #[derive(Clone, Debug)]
-// pub struct LineShape {
-//     // id: ShapeTypeId,
-//     selected: bool,
-//     va_id: VertexId,
-//     vb_id: VertexId,
-// }
-
-// impl ApiShapes for LineShape {
-//     fn get_id(&self) -> ShapeTypeId {
-//         self.id
-//     }
-//     fn is_selected(&self) -> bool {
-//         self.selected
-//     }
-//     fn set_selected(&mut self, selection: bool) {
-//         self.selected = selection
-//     }
-//     fn set_all_vertices(&mut self, selection: bool, vertices_pool: &mut HashMap<VertexId, Vertex>) {
-//         let va = vertices_pool.get_mut(&self.va_id).unwrap();
-//         va.selected = selection;
-//         let vb = vertices_pool.get_mut(&self.vb_id).unwrap();
-//         vb.selected = selection;
-//         self.move_selection_end(vertices_pool);
-//     }
-//     fn get_vertex_selected(&self, vertices_pool: &HashMap<VertexId, Vertex>) -> Option<VertexId> {
-//         let va = vertices_pool.get(&self.va_id).unwrap();
-//         let vb = vertices_pool.get(&self.vb_id).unwrap();
-//         match (va.selected, vb.selected) {
-//             (true, false) => Some(self.va_id),
-//             (false, true) => Some(self.vb_id),
-//             _ => None,
-//         }
-//     }
-//     fn get_vertex_under_pos(
-//         &self,
-//         pt: &Point,
-//         grab_handle_precision: f64,
-//         vertices_pool: &HashMap<VertexId, Vertex>,
-//     ) -> Option<VertexId> {
-//         let va = vertices_pool.get(&self.va_id).unwrap();
-//         let vb = vertices_pool.get(&self.vb_id).unwrap();
-//         if va.pt.distance(*pt) < grab_handle_precision {
-//             return Some(self.va_id);
-//         }
-//         if vb.pt.distance(*pt) < grab_handle_precision {
-//             return Some(self.vb_id);
-//         }
-//         None
-//     }
-//     fn select_vertex_under_pos(
-//         &mut self,
-//         pt: &Point,
-//         grab_handle_precision: f64,
-//         vertices_pool: &mut HashMap<VertexId, Vertex>,
-//     ) {
-//         let va = vertices_pool.get_mut(&self.va_id).unwrap();
-//         if va.pt.distance(*pt) < grab_handle_precision {
-//             va.saved_pt = va.pt;
-//             va.selected = true;
-//             return;
-//         }
-//         let vb = vertices_pool.get_mut(&self.vb_id).unwrap();
-//         if vb.pt.distance(*pt) < grab_handle_precision {
-//             vb.saved_pt = vb.pt;
-//             vb.selected = true;
-//             return;
-//         }
-//     }
-//     fn move_selection(&mut self, dpt: &Point, vertices_pool: &mut HashMap<VertexId, Vertex>) {
-//         if self.selected {
-//             let va_sel = vertices_pool.get(&self.va_id).unwrap().selected;
-//             let vb_sel = vertices_pool.get(&self.vb_id).unwrap().selected;
-//             match (va_sel, vb_sel) {
-//                 (false, false) => {
-//                     let va = vertices_pool.get_mut(&self.va_id).unwrap();
-//                     va.pt = va.saved_pt + (dpt.x, dpt.y);
-
-//                     let vb = vertices_pool.get_mut(&self.vb_id).unwrap();
-//                     vb.pt = vb.saved_pt + (dpt.x, dpt.y);
-//                 }
-//                 (true, false) => {
-//                     let va = vertices_pool.get_mut(&self.va_id).unwrap();
-//                     va.pt = va.saved_pt + (dpt.x, dpt.y);
-//                 }
-//                 (false, true) => {
-//                     let vb = vertices_pool.get_mut(&self.vb_id).unwrap();
-//                     vb.pt = vb.saved_pt + (dpt.x, dpt.y);
-//                 }
-//                 _ => (),
-//             }
-//         }
-//     }
-//     fn move_selection_end(&mut self, vertices_pool: &mut HashMap<VertexId, Vertex>) {
-//         let va = vertices_pool.get_mut(&self.va_id).unwrap();
-//         va.saved_pt = va.pt;
-//         let vb = vertices_pool.get_mut(&self.vb_id).unwrap();
-//         vb.saved_pt = vb.pt;
-//     }
-//     fn get_handles_vertices(&self, vertices_pool: &HashMap<VertexId, Vertex>) -> Vec<Vertex> {
-//         let va = vertices_pool.get(&self.va_id).unwrap();
-//         let vb = vertices_pool.get(&self.vb_id).unwrap();
-//         vec![*va, *vb]
-//     }
-//     fn get_path(&self, tol: f64, vertices_pool: &HashMap<VertexId, Vertex>) -> BezPath {
-//         let va = vertices_pool.get(&self.va_id).unwrap();
-//         let vb = vertices_pool.get(&self.vb_id).unwrap();
-//         Line::new(va.pt, vb.pt).into_path(tol)
-//     }
-// }
-
-// #[derive(Clone, Debug)]
-// pub enum ShapeType {
-//     STLine(LineShape),
-// }
-// impl ShapeType {
-//     pub fn new_line(line: LineShape) -> ShapeType {
-//         ShapeType::STLine(line)
-//     }
-//     pub fn get_bez_path(&self, tol: f64, vertices_pool: &HashMap<VertexId, Vertex>) -> BezPath {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.get_path(tol, vertices_pool),
-//         }
-//     }
-//     pub fn get_id(&self) -> ShapeTypeId {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.id,
-//         }
-//     }
-//     pub fn is_selected(&self) -> bool {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.is_selected(),
-//         }
-//     }
-//     pub fn set_selected(&mut self, selection: bool) {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.set_selected(selection),
-//         };
-//     }
-//     pub fn set_vertices_selection(
-//         &mut self,
-//         selection: bool,
-//         vertices_pool: &mut HashMap<VertexId, Vertex>,
-//     ) {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.set_all_vertices(selection, vertices_pool),
-//         };
-//     }
-//     pub fn get_vertex_selected(
-//         &self,
-//         vertices_pool: &HashMap<VertexId, Vertex>,
-//     ) -> Option<VertexId> {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.get_vertex_selected(vertices_pool),
-//         };
-//         None
-//     }
-//     pub fn get_bounded_rectangle(&self) -> [Point; 2] {
-//         // TODO
-//         [Point::ZERO, Point::ZERO]
-//     }
-//     pub fn get_vertex_under_pos(
-//         &self,
-//         pick_pos: &Point,
-//         grab_handle_precision: f64,
-//         vertices_pool: &HashMap<VertexId, Vertex>,
-//     ) -> Option<VertexId> {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => {
-//                 line_shape.get_vertex_under_pos(&pick_pos, grab_handle_precision, vertices_pool)
-//             }
-//         }
-//     }
-//     pub fn select_vertex_under_pos(
-//         &mut self,
-//         pick_pos: &Point,
-//         grab_handle_precision: f64,
-//         vertices_pool: &mut HashMap<VertexId, Vertex>,
-//     ) {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => {
-//                 line_shape.select_vertex_under_pos(&pick_pos, grab_handle_precision, vertices_pool)
-//             }
-//         }
-//     }
-//     pub fn move_selection(&mut self, dpos: &Point, vertices_pool: &mut HashMap<VertexId, Vertex>) {
-//         use ShapeType::*;
-//         match self {
-//             STLine(line_shape) => line_shape.move_selection(dpos, vertices_pool),
-//         };
-//     }
-// }
+        (vals[3] - vals[1]).powi(2) + (vals[2] - vals[0]).powi(2) - self.sq_distance_value
+    }
+}
+impl Constraint for BindDistance {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.va_id, self.vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn jacobian(&self, vals: &[f64], jac: &mut [f64]) {
+        let (dx, dy) = (vals[2] - vals[0], vals[3] - vals[1]);
+        jac.copy_from_slice(&[-2. * dx, -2. * dy, 2. * dx, 2. * dy]);
+    }
+}
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindPerpendicular {
+    pub id: BindingId,
+    pub props: BindProps,
+    pub l1va_id: VertexId,
+    pub l1vb_id: VertexId,
+    pub l2va_id: VertexId,
+    pub l2vb_id: VertexId,
+}
+impl BindPerpendicular {
+    pub fn bind(&self, vals: &[f64; 8]) -> f64 {
+        (vals[2] - vals[0]) * (vals[6] - vals[4]) + (vals[3] - vals[1]) * (vals[7] - vals[5])
+    }
+}
+impl Constraint for BindPerpendicular {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.l1va_id, self.l1vb_id, self.l2va_id, self.l2vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindEqualLength {
+    pub id: BindingId,
+    pub props: BindProps,
+    pub l1va_id: VertexId,
+    pub l1vb_id: VertexId,
+    pub l2va_id: VertexId,
+    pub l2vb_id: VertexId,
+}
+impl BindEqualLength {
+    pub fn bind(&self, vals: &[f64; 8]) -> f64 {
+        (vals[2] - vals[0]).powi(2) + (vals[3] - vals[1]).powi(2)
+            - (vals[6] - vals[4]).powi(2)
+            - (vals[7] - vals[5]).powi(2)
+    }
+}
+impl Constraint for BindEqualLength {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.l1va_id, self.l1vb_id, self.l2va_id, self.l2vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindCoincident {
+    pub id: BindingId,
+    pub props: BindProps,
+    pub va_id: VertexId,
+    pub vb_id: VertexId,
+}
+impl BindCoincident {
+    pub fn bind(&self, vals: &[f64; 4]) -> [f64; 2] {
+        [vals[0] - vals[2], vals[1] - vals[3]]
+    }
+}
+impl Constraint for BindCoincident {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.va_id, self.vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        2
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        let bind = self.bind(vals.try_into().unwrap());
+        rx.copy_from_slice(&bind);
+    }
+    fn jacobian(&self, _vals: &[f64], jac: &mut [f64]) {
+        jac.copy_from_slice(&[1., 0., -1., 0., 0., 1., 0., -1.]);
+    }
+}
+
+// Distance between two vertices within [min_value, max_value], either one can be
+// 0 or infinite to only keep a maximum or a minimum
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindDistanceRange {
+    pub id: BindingId,
+    pub props: BindProps,
+    #[serde(with = "float")]
+    pub min_value: f64,
+    // Infinite for a minimum distance only
+    #[serde(with = "float")]
+    pub max_value: f64,
+    pub va_id: VertexId,
+    pub vb_id: VertexId,
+}
+impl BindDistanceRange {
+    // Squared distance, bounded by the squared min and max
+    pub fn bind(&self, vals: &[f64; 4]) -> f64 {
+        (vals[3] - vals[1]).powi(2) + (vals[2] - vals[0]).powi(2)
+    }
+}
+impl Constraint for BindDistanceRange {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.va_id, self.vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn jacobian(&self, vals: &[f64], jac: &mut [f64]) {
+        let (dx, dy) = (vals[2] - vals[0], vals[3] - vals[1]);
+        jac.copy_from_slice(&[-2. * dx, -2. * dy, 2. * dx, 2. * dy]);
+    }
+    fn bounds(&self) -> Option<(f64, f64)> {
+        Some((self.min_value.powi(2), self.max_value.powi(2)))
+    }
+}
+
+// Vertex v stays on one side of the line (la, lb), side is 1 for the left and -1
+// for the right, looking from la to lb
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindHalfPlane {
+    pub id: BindingId,
+    pub props: BindProps,
+    pub side: f64,
+    pub v_id: VertexId,
+    pub la_id: VertexId,
+    pub lb_id: VertexId,
+}
+impl BindHalfPlane {
+    // vals: v, la, lb. Positive on the expected side
+    pub fn bind(&self, vals: &[f64; 6]) -> f64 {
+        self.side
+            * ((vals[4] - vals[2]) * (vals[1] - vals[3])
+                - (vals[5] - vals[3]) * (vals[0] - vals[2]))
+    }
+}
+impl Constraint for BindHalfPlane {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.v_id, self.la_id, self.lb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn bounds(&self) -> Option<(f64, f64)> {
+        Some((0., f64::INFINITY))
+    }
+}
+
+// Signed angle from the first segment to the second within [min_angle, max_angle],
+// in radians in ]-PI, PI]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BindAngleRange {
+    pub id: BindingId,
+    pub props: BindProps,
+    #[serde(with = "float")]
+    pub min_angle: f64,
+    #[serde(with = "float")]
+    pub max_angle: f64,
+    pub l1va_id: VertexId,
+    pub l1vb_id: VertexId,
+    pub l2va_id: VertexId,
+    pub l2vb_id: VertexId,
+}
+impl BindAngleRange {
+    pub fn bind(&self, vals: &[f64; 8]) -> f64 {
+        let (dx1, dy1) = (vals[2] - vals[0], vals[3] - vals[1]);
+        let (dx2, dy2) = (vals[6] - vals[4], vals[7] - vals[5]);
+        (dx1 * dy2 - dy1 * dx2).atan2(dx1 * dx2 + dy1 * dy2)
+    }
+}
+impl Constraint for BindAngleRange {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.l1va_id, self.l1vb_id, self.l2va_id, self.l2vb_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = self.bind(vals.try_into().unwrap());
+    }
+    fn bounds(&self) -> Option<(f64, f64)> {
+        Some((self.min_angle, self.max_angle))
+    }
+}
+
+// A binding defined outside of the crate by its Constraint
+#[derive(Clone, Debug)]
+pub struct BindCustom {
+    pub id: BindingId,
+    pub props: BindProps,
+    pub constraint: Arc<dyn Constraint>,
+}
+impl BindCustom {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(ser::Error::custom(format!(
+            "custom binding {} can't be saved",
+            *self.id
+        )))
+    }
+}
+impl PartialEq for BindCustom {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.props == other.props
+            && Arc::ptr_eq(&self.constraint, &other.constraint)
+    }
+}
+impl Constraint for BindCustom {
+    fn v_ids(&self) -> Vec<VertexId> {
+        self.constraint.v_ids()
+    }
+    fn eq_count(&self) -> usize {
+        self.constraint.eq_count()
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        self.constraint.residuals(vals, rx)
+    }
+    fn jacobian(&self, vals: &[f64], jac: &mut [f64]) {
+        self.constraint.jacobian(vals, jac)
+    }
+    fn bounds(&self) -> Option<(f64, f64)> {
+        self.constraint.bounds()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LineShape {
+    pub id: ShapeTypeId,
+    pub selected: bool,
+    pub va_id: VertexId,
+    pub vb_id: VertexId,
+}
+impl LineShape {
+    pub fn get_path(&self, tol: f64, vertices_pool: &VerticesPool) -> BezPath {
+        let va = &vertices_pool[&self.va_id];
+        let vb = &vertices_pool[&self.vb_id];
+        Line::new(va.pt, vb.pt).into_path(tol)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ShapeType {
+    STLine(LineShape),
+}
+impl ShapeType {
+    pub fn new_line(line: LineShape) -> ShapeType {
+        ShapeType::STLine(line)
+    }
+    pub fn get_id(&self) -> ShapeTypeId {
+        use ShapeType::*;
+        match self {
+            STLine(line_shape) => line_shape.id,
+        }
+    }
+    pub fn get_v_ids(&self) -> Vec<VertexId> {
+        use ShapeType::*;
+        match self {
+            STLine(line_shape) => vec![line_shape.va_id, line_shape.vb_id],
+        }
+    }
+    pub fn get_bez_path(&self, tol: f64, vertices_pool: &VerticesPool) -> BezPath {
+        use ShapeType::*;
+        match self {
+            STLine(line_shape) => line_shape.get_path(tol, vertices_pool),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Vertex {
     pub id: VertexId,
     pub pt: Point,
@@ -398,6 +832,9 @@ pub struct Vertex {
     pub magnetic: bool,
     pub draggable: bool,
     pub selected: bool,
+    // Box the solver keeps the vertex in, e.g. the sheet, infinite on the free sides
+    #[serde(with = "float::bounds")]
+    pub bounds: Option<Rect>,
 }
 impl Vertex {
     // pub fn magnetic(mut self, magnetic: bool) -> Self {
@@ -417,150 +854,519 @@ impl Vertex {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct BindingsPool(HashMap<BindingId, Binding>);
+// Ids are allocated by the pool from its own counter and never reused, so the
+// numbering only depends on the pool history and a stale id can't alias a new binding.
+// Bindings are kept sorted by id, which is the order of the equations in the solver.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BindingsPool {
+    bindings: BTreeMap<BindingId, Binding>,
+    next_id: usize,
+}
 impl Deref for BindingsPool {
-    type Target = HashMap<BindingId, Binding>;
+    type Target = BTreeMap<BindingId, Binding>;
     fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-impl DerefMut for BindingsPool {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &self.bindings
     }
 }
-#[allow(dead_code)]
 impl BindingsPool {
     pub fn new() -> BindingsPool {
-        BindingsPool(HashMap::new())
+        BindingsPool {
+            bindings: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+    // Id the next added binding gets, after the ones in the pool as a pool read
+    // from a document may come with a stale counter
+    fn next_id(&self) -> BindingId {
+        let after_last = self.bindings.keys().next_back().map_or(0, |id| **id + 1);
+        BindingId(self.next_id.max(after_last))
+    }
+    // Insert the binding under the next id once checked against the vertices it is
+    // built on, as given to the add_bind_* functions
+    fn insert_checked(&mut self, bind: Binding, vertices: &[&Vertex]) -> Result<(), BindingError> {
+        self.add_binding(bind, &VerticesPool::of(vertices))
+            .map(|_| ())
+    }
+    // Insert a binding built outside of the pool under a newly allocated id, once
+    // checked against the vertices of v_pool
+    pub fn add_binding(
+        &mut self,
+        mut bind: Binding,
+        v_pool: &VerticesPool,
+    ) -> Result<BindingId, BindingError> {
+        let id = self.next_id();
+        bind.set_id(id);
+        self.check(&bind, v_pool)?;
+        self.bindings.insert(id, bind);
+        self.next_id = *id + 1;
+        Ok(id)
+    }
+    // Put back a removed binding, or replace one, under its own id. Return false
+    // if the pool never gave the id, which is left for the next added binding
+    pub fn restore(&mut self, bind: Binding) -> bool {
+        if bind.get_id() >= self.next_id() {
+            return false;
+        }
+        self.bindings.insert(bind.get_id(), bind);
+        true
+    }
+    // Edit the binding in place, its id and its vertices must not change
+    pub fn get_mut(&mut self, id: &BindingId) -> Option<&mut Binding> {
+        self.bindings.get_mut(id)
+    }
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Binding> {
+        self.bindings.values_mut()
     }
-    pub fn add_bind_fixed(&mut self, v: &Vertex) -> BindFixed {
-        let id = BindingId::new_id();
+    // Whether the binding can join the pool, its vertices being in v_pool: a segment
+    // needs two distinct vertices, the segments of a binding must differ and have a
+    // length to be parallel, a reference needs a dimension. A binding on the same
+    // vertices as an existing one of the same kind is a duplicate, but for the
+    // reference ones, which only measure
+    pub fn check(&self, bind: &Binding, v_pool: &VerticesPool) -> Result<(), BindingError> {
+        let mut v_ids = BTreeSet::new();
+        bind.get_v_ids(&mut v_ids);
+        if let Some(v_id) = v_ids.iter().find(|v_id| !v_pool.contains_key(v_id)) {
+            return Err(BindingError::UnknownVertex(*v_id));
+        }
+        let segments = bind.segments();
+        if let Some((va_id, _)) = segments.iter().find(|(va_id, vb_id)| va_id == vb_id) {
+            return Err(BindingError::IdenticalEndpoints(*va_id));
+        }
+        if let [(l1va_id, l1vb_id), (l2va_id, l2vb_id)] = segments[..] {
+            if (l1va_id.min(l1vb_id), l1va_id.max(l1vb_id))
+                == (l2va_id.min(l2vb_id), l2va_id.max(l2vb_id))
+            {
+                return Err(BindingError::SameSegment(l1va_id, l1vb_id));
+            }
+        }
+        let length =
+            |(va_id, vb_id): &(VertexId, VertexId)| v_pool[va_id].pt.distance(v_pool[vb_id].pt);
+        if let Binding::Parallel(_) = bind {
+            if segments.iter().any(|seg| length(seg) == 0.) {
+                return Err(BindingError::DegenerateParallel);
+            }
+        }
+        if bind.get_props().reference && !bind.is_measurable() {
+            return Err(BindingError::NotMeasurable(bind.get_id()));
+        }
+        if bind.get_props().reference {
+            return Ok(());
+        }
+        let Some(signature) = bind.signature() else {
+            return Ok(());
+        };
+        match self.values().find(|other| {
+            other.get_id() != bind.get_id()
+                && !other.get_props().reference
+                && other.signature().as_ref() == Some(&signature)
+        }) {
+            Some(other) => Err(BindingError::Duplicate(other.get_id())),
+            None => Ok(()),
+        }
+    }
+    pub fn remove_binding(&mut self, id: &BindingId) -> Option<Binding> {
+        self.bindings.remove(id)
+    }
+    // Ids of the bindings that reference the vertex
+    pub fn bindings_of(&self, v_id: &VertexId) -> Vec<BindingId> {
+        self.values()
+            .filter(|bind| bind.uses_vertex(v_id))
+            .map(|bind| bind.get_id())
+            .collect()
+    }
+    // The bindings that add equations to the system, the reference and the
+    // disabled ones left out
+    pub fn driving(&self) -> impl Iterator<Item = &Binding> {
+        self.values().filter(|bind| bind.get_props().is_driving())
+    }
+    // Report every reference to a vertex missing from v_pool
+    pub fn validate(&self, v_pool: &VerticesPool) -> Result<(), Vec<DanglingRef>> {
+        let mut dangling = vec![];
+        self.values().for_each(|bind| {
+            let mut v_ids = BTreeSet::new();
+            bind.get_v_ids(&mut v_ids);
+            v_ids
+                .into_iter()
+                .filter(|v_id| !v_pool.contains_key(v_id))
+                .for_each(|v_id| {
+                    dangling.push(DanglingRef {
+                        binding_id: bind.get_id(),
+                        v_id,
+                    })
+                });
+        });
+        if dangling.is_empty() {
+            Ok(())
+        } else {
+            Err(dangling)
+        }
+    }
+    pub fn add_bind_fixed(&mut self, v: &Vertex) -> Result<BindFixed, BindingError> {
+        let id = self.next_id();
         let bind = BindFixed {
             id,
+            props: BindProps::default(),
             fixed_value: v.pt,
             v_id: v.id,
         };
-        self.insert(id, Binding::Fixed(bind.clone()));
-        bind
+        self.insert_checked(Binding::Fixed(bind), &[v])?;
+        Ok(bind)
     }
-    pub fn add_bind_fixed_x(&mut self, v: &Vertex) -> BindFixedX {
-        let id = BindingId::new_id();
+    pub fn add_bind_fixed_x(&mut self, v: &Vertex) -> Result<BindFixedX, BindingError> {
+        let id = self.next_id();
         let bind = BindFixedX {
             id,
+            props: BindProps::default(),
             fixed_value: v.pt.x,
             v_id: v.id,
         };
-        self.insert(id, Binding::FixedX(bind.clone()));
-        bind
+        self.insert_checked(Binding::FixedX(bind), &[v])?;
+        Ok(bind)
     }
-    pub fn add_bind_fixed_y(&mut self, v: &Vertex) -> BindFixedY {
-        let id = BindingId::new_id();
+    pub fn add_bind_fixed_y(&mut self, v: &Vertex) -> Result<BindFixedY, BindingError> {
+        let id = self.next_id();
         let bind = BindFixedY {
             id,
+            props: BindProps::default(),
             fixed_value: v.pt.y,
             v_id: v.id,
         };
-        self.insert(id, Binding::FixedY(bind.clone()));
-        bind
+        self.insert_checked(Binding::FixedY(bind), &[v])?;
+        Ok(bind)
     }
-    pub fn add_bind_vertical(&mut self, seg: (&Vertex, &Vertex)) -> BindVertical {
-        let id = BindingId::new_id();
+    pub fn add_bind_vertical(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+    ) -> Result<BindVertical, BindingError> {
+        let id = self.next_id();
         let bind = BindVertical {
             id,
+            props: BindProps::default(),
             va_id: seg.0.id,
             vb_id: seg.1.id,
         };
-        self.insert(id, Binding::Vertical(bind.clone()));
-        bind
+        self.insert_checked(Binding::Vertical(bind), &[seg.0, seg.1])?;
+        Ok(bind)
     }
-    pub fn add_bind_horizontal(&mut self, seg: (&Vertex, &Vertex)) -> BindHorizontal {
-        let id = BindingId::new_id();
+    pub fn add_bind_horizontal(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+    ) -> Result<BindHorizontal, BindingError> {
+        let id = self.next_id();
         let bind = BindHorizontal {
             id,
+            props: BindProps::default(),
             va_id: seg.0.id,
             vb_id: seg.1.id,
         };
-        self.insert(id, Binding::Horizontal(bind.clone()));
-        bind
+        self.insert_checked(Binding::Horizontal(bind), &[seg.0, seg.1])?;
+        Ok(bind)
     }
     pub fn add_bind_parallel(
         &mut self,
         seg1: (&Vertex, &Vertex),
         seg2: (&Vertex, &Vertex),
-    ) -> BindParallel {
-        let id = BindingId::new_id();
+    ) -> Result<BindParallel, BindingError> {
+        let id = self.next_id();
         let bind = BindParallel {
             id,
+            props: BindProps::default(),
             l1va_id: seg1.0.id,
             l1vb_id: seg1.1.id,
             l2va_id: seg2.0.id,
             l2vb_id: seg2.1.id,
         };
-        self.insert(id, Binding::Parallel(bind.clone()));
-        bind
+        self.insert_checked(Binding::Parallel(bind), &[seg1.0, seg1.1, seg2.0, seg2.1])?;
+        Ok(bind)
     }
-    pub fn add_bind_distance(&mut self, seg: (&Vertex, &Vertex)) -> BindDistance {
-        let id = BindingId::new_id();
+    pub fn add_bind_distance(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+    ) -> Result<BindDistance, BindingError> {
+        let id = self.next_id();
         let bind = BindDistance {
             id,
+            props: BindProps::default(),
             sq_distance_value: seg.0.dist_sq(seg.1),
             va_id: seg.0.id,
             vb_id: seg.1.id,
         };
-        self.insert(id, Binding::Distance(bind.clone()));
-        bind
-    }
-}
-
-// pub struct ShapesPool(HashMap<ShapeTypeId, ShapeType>);
-// impl Deref for ShapesPool {
-//     type Target = HashMap<ShapeTypeId, ShapeType>;
-//     fn deref(&self) -> &Self::Target {
-//         &self.0
-//     }
-// }
-// impl DerefMut for ShapesPool {
-//     fn deref_mut(&mut self) -> &mut Self::Target {
-//         &mut self.0
-//     }
-// }
-// impl ShapesPool {
-//     pub fn new() -> ShapesPool {
-//         ShapesPool(HashMap::new())
-//     }
-//     pub fn add_line(&mut self, va: &Vertex, vb: &Vertex) -> LineShape {
-//         let id: ShapeTypeId = ShapeTypeId::new_id();
-//         let line = LineShape {
-//             id,
-//             selected: false,
-//             va_id: va.id,
-//             vb_id: vb.id,
-//         };
-//         self.insert(id, ShapeType::STLine(line.clone()));
-//         line
-//     }
-// }
-
-pub struct VerticesPool(HashMap<VertexId, Vertex>);
-impl Deref for VerticesPool {
-    type Target = HashMap<VertexId, Vertex>;
+        self.insert_checked(Binding::Distance(bind), &[seg.0, seg.1])?;
+        Ok(bind)
+    }
+    pub fn add_bind_perpendicular(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+    ) -> Result<BindPerpendicular, BindingError> {
+        let id = self.next_id();
+        let bind = BindPerpendicular {
+            id,
+            props: BindProps::default(),
+            l1va_id: seg1.0.id,
+            l1vb_id: seg1.1.id,
+            l2va_id: seg2.0.id,
+            l2vb_id: seg2.1.id,
+        };
+        self.insert_checked(
+            Binding::Perpendicular(bind),
+            &[seg1.0, seg1.1, seg2.0, seg2.1],
+        )?;
+        Ok(bind)
+    }
+    pub fn add_bind_equal_length(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+    ) -> Result<BindEqualLength, BindingError> {
+        let id = self.next_id();
+        let bind = BindEqualLength {
+            id,
+            props: BindProps::default(),
+            l1va_id: seg1.0.id,
+            l1vb_id: seg1.1.id,
+            l2va_id: seg2.0.id,
+            l2vb_id: seg2.1.id,
+        };
+        self.insert_checked(
+            Binding::EqualLength(bind),
+            &[seg1.0, seg1.1, seg2.0, seg2.1],
+        )?;
+        Ok(bind)
+    }
+    pub fn add_bind_coincident(
+        &mut self,
+        va: &Vertex,
+        vb: &Vertex,
+    ) -> Result<BindCoincident, BindingError> {
+        let id = self.next_id();
+        let bind = BindCoincident {
+            id,
+            props: BindProps::default(),
+            va_id: va.id,
+            vb_id: vb.id,
+        };
+        self.insert_checked(Binding::Coincident(bind), &[va, vb])?;
+        Ok(bind)
+    }
+    pub fn add_bind_distance_range(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+        min_value: f64,
+        max_value: f64,
+    ) -> Result<BindDistanceRange, BindingError> {
+        let id = self.next_id();
+        let bind = BindDistanceRange {
+            id,
+            props: BindProps::default(),
+            min_value,
+            max_value,
+            va_id: seg.0.id,
+            vb_id: seg.1.id,
+        };
+        self.insert_checked(Binding::DistanceRange(bind), &[seg.0, seg.1])?;
+        Ok(bind)
+    }
+    // Keep v on the side of the line where it is now
+    pub fn add_bind_half_plane(
+        &mut self,
+        v: &Vertex,
+        line: (&Vertex, &Vertex),
+    ) -> Result<BindHalfPlane, BindingError> {
+        let id = self.next_id();
+        let (la, lb) = (line.0.pt, line.1.pt);
+        let cross = (lb.x - la.x) * (v.pt.y - la.y) - (lb.y - la.y) * (v.pt.x - la.x);
+        let bind = BindHalfPlane {
+            id,
+            props: BindProps::default(),
+            side: if cross < 0. { -1. } else { 1. },
+            v_id: v.id,
+            la_id: line.0.id,
+            lb_id: line.1.id,
+        };
+        self.insert_checked(Binding::HalfPlane(bind), &[v, line.0, line.1])?;
+        Ok(bind)
+    }
+    pub fn add_bind_angle_range(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+        min_angle: f64,
+        max_angle: f64,
+    ) -> Result<BindAngleRange, BindingError> {
+        let id = self.next_id();
+        let bind = BindAngleRange {
+            id,
+            props: BindProps::default(),
+            min_angle,
+            max_angle,
+            l1va_id: seg1.0.id,
+            l1vb_id: seg1.1.id,
+            l2va_id: seg2.0.id,
+            l2vb_id: seg2.1.id,
+        };
+        self.insert_checked(Binding::AngleRange(bind), &[seg1.0, seg1.1, seg2.0, seg2.1])?;
+        Ok(bind)
+    }
+    // The vertices of the constraint are looked up in v_pool
+    pub fn add_bind_custom(
+        &mut self,
+        constraint: Arc<dyn Constraint>,
+        v_pool: &VerticesPool,
+    ) -> Result<BindCustom, BindingError> {
+        let bind = BindCustom {
+            id: self.next_id(),
+            props: BindProps::default(),
+            constraint,
+        };
+        self.add_binding(Binding::Custom(bind.clone()), v_pool)?;
+        Ok(bind)
+    }
+}
+
+// Ids allocated like BindingsPool
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ShapesPool {
+    shapes: BTreeMap<ShapeTypeId, ShapeType>,
+    next_id: usize,
+}
+impl Deref for ShapesPool {
+    type Target = BTreeMap<ShapeTypeId, ShapeType>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.shapes
     }
 }
-impl DerefMut for VerticesPool {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl ShapesPool {
+    pub fn new() -> ShapesPool {
+        ShapesPool {
+            shapes: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+    // See BindingsPool::next_id
+    fn next_id(&self) -> ShapeTypeId {
+        let after_last = self.shapes.keys().next_back().map_or(0, |id| **id + 1);
+        ShapeTypeId(self.next_id.max(after_last))
+    }
+    fn new_id(&mut self) -> ShapeTypeId {
+        let id = self.next_id();
+        self.next_id = *id + 1;
+        id
+    }
+    pub fn add_line(&mut self, va: &Vertex, vb: &Vertex) -> LineShape {
+        let id = self.new_id();
+        let line = LineShape {
+            id,
+            selected: false,
+            va_id: va.id,
+            vb_id: vb.id,
+        };
+        self.shapes.insert(id, ShapeType::STLine(line.clone()));
+        line
+    }
+    // See BindingsPool::restore
+    pub fn restore(&mut self, shape: ShapeType) -> bool {
+        if shape.get_id() >= self.next_id() {
+            return false;
+        }
+        self.shapes.insert(shape.get_id(), shape);
+        true
+    }
+    pub fn remove_shape(&mut self, id: &ShapeTypeId) -> Option<ShapeType> {
+        self.shapes.remove(id)
+    }
+    // Ids of the shapes built on the vertex
+    pub fn shapes_of(&self, v_id: &VertexId) -> Vec<ShapeTypeId> {
+        self.values()
+            .filter(|shape| shape.get_v_ids().contains(v_id))
+            .map(|shape| shape.get_id())
+            .collect()
+    }
+}
+
+// Ids allocated like BindingsPool
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VerticesPool {
+    vertices: BTreeMap<VertexId, Vertex>,
+    next_id: usize,
+}
+impl Deref for VerticesPool {
+    type Target = BTreeMap<VertexId, Vertex>;
+    fn deref(&self) -> &Self::Target {
+        &self.vertices
     }
 }
 impl VerticesPool {
     pub fn new() -> VerticesPool {
-        VerticesPool(HashMap::new())
+        VerticesPool {
+            vertices: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+    // See BindingsPool::next_id
+    fn next_id(&self) -> VertexId {
+        let after_last = self.vertices.keys().next_back().map_or(0, |id| **id + 1);
+        VertexId(self.next_id.max(after_last))
+    }
+    fn new_id(&mut self) -> VertexId {
+        let id = self.next_id();
+        self.next_id = *id + 1;
+        id
+    }
+    // See BindingsPool::restore
+    pub fn restore(&mut self, vertex: Vertex) -> bool {
+        if vertex.id >= self.next_id() {
+            return false;
+        }
+        self.vertices.insert(vertex.id, vertex);
+        true
+    }
+    // Move the vertex, return where it was or None if there is no such vertex
+    pub fn set_pt(&mut self, v_id: &VertexId, pt: Point) -> Option<Point> {
+        let v = self.vertices.get_mut(v_id)?;
+        Some(std::mem::replace(&mut v.pt, pt))
+    }
+    // Return false if there is no such vertex
+    pub fn set_bounds(&mut self, v_id: &VertexId, bounds: Option<Rect>) -> bool {
+        match self.vertices.get_mut(v_id) {
+            Some(v) => {
+                v.bounds = bounds;
+                true
+            }
+            None => false,
+        }
+    }
+    // Remove the vertex. The bindings referencing it are removed too when cascade is
+    // set, otherwise the vertex is kept and these bindings are reported in the error
+    pub fn remove_vertex(
+        &mut self,
+        v_id: &VertexId,
+        bindings_pool: &mut BindingsPool,
+        cascade: bool,
+    ) -> Result<(Vertex, Vec<Binding>), RemoveError> {
+        if !self.contains_key(v_id) {
+            return Err(RemoveError::UnknownVertex(*v_id));
+        }
+        let dependents = bindings_pool.bindings_of(v_id);
+        if !cascade && !dependents.is_empty() {
+            return Err(RemoveError::HasDependents(dependents));
+        }
+        let removed = dependents
+            .iter()
+            .filter_map(|id| bindings_pool.remove_binding(id))
+            .collect();
+        Ok((self.vertices.remove(v_id).unwrap(), removed))
+    }
+    // Pool of copies of the vertices, e.g. to check a binding built on them
+    pub fn of(vertices: &[&Vertex]) -> VerticesPool {
+        let mut v_pool = VerticesPool::new();
+        vertices
+            .iter()
+            .for_each(|v| _ = v_pool.vertices.insert(v.id, **v));
+        v_pool
     }
     pub fn add(&mut self, pt: Point) -> Vertex {
-        let id = VertexId::new_id();
+        let id = self.new_id();
         let v = Vertex {
             id,
             pt,
@@ -568,20 +1374,55 @@ impl VerticesPool {
             magnetic: true,
             draggable: true,
             selected: false,
+            bounds: None,
         };
-        self.insert(id, v);
+        self.vertices.insert(id, v);
         v
     }
 }
 
-static COUNTER_BINDINGS: AtomicUsize = AtomicUsize::new(0);
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-pub struct BindingId(usize);
-impl BindingId {
-    pub fn new_id() -> BindingId {
-        BindingId(COUNTER_BINDINGS.fetch_add(1, Ordering::Relaxed))
+// A binding referencing a vertex that is not in the vertices pool
+#[derive(Copy, Clone, Debug)]
+pub struct DanglingRef {
+    pub binding_id: BindingId,
+    pub v_id: VertexId,
+}
+impl fmt::Display for DanglingRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "binding {} references unknown vertex {}",
+            *self.binding_id, *self.v_id
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RemoveError {
+    UnknownVertex(VertexId),
+    HasDependents(Vec<BindingId>),
+    UsedByShapes(Vec<ShapeTypeId>),
+}
+impl fmt::Display for RemoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoveError::UnknownVertex(v_id) => write!(f, "unknown vertex {}", **v_id),
+            RemoveError::HasDependents(ids) => write!(
+                f,
+                "vertex is used by bindings {:?}",
+                ids.iter().map(|id| **id).collect::<Vec<_>>()
+            ),
+            RemoveError::UsedByShapes(ids) => write!(
+                f,
+                "vertex is used by shapes {:?}",
+                ids.iter().map(|id| **id).collect::<Vec<_>>()
+            ),
+        }
     }
 }
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BindingId(usize);
 impl Deref for BindingId {
     type Target = usize;
     fn deref(&self) -> &Self::Target {
@@ -594,34 +1435,22 @@ impl DerefMut for BindingId {
     }
 }
 
-// static COUNTER_SHAPES: AtomicUsize = AtomicUsize::new(0);
-// #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-// pub struct ShapeTypeId(usize);
-// impl ShapeTypeId {
-//     pub fn new_id() -> ShapeTypeId {
-//         ShapeTypeId(COUNTER_SHAPES.fetch_add(1, Ordering::Relaxed))
-//     }
-// }
-// impl Deref for ShapeTypeId {
-//     type Target = usize;
-//     fn deref(&self) -> &Self::Target {
-//         &self.0
-//     }
-// }
-// impl DerefMut for ShapeTypeId {
-//     fn deref_mut(&mut self) -> &mut Self::Target {
-//         &mut self.0
-//     }
-// }
-
-static COUNTER_VERTICES: AtomicUsize = AtomicUsize::new(0);
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-pub struct VertexId(usize);
-impl VertexId {
-    pub fn new_id() -> VertexId {
-        VertexId(COUNTER_VERTICES.fetch_add(1, Ordering::Relaxed))
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ShapeTypeId(usize);
+impl Deref for ShapeTypeId {
+    type Target = usize;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl DerefMut for ShapeTypeId {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
     }
 }
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct VertexId(usize);
 impl Deref for VertexId {
     type Target = usize;
     fn deref(&self) -> &Self::Target {
@@ -633,3 +1462,78 @@ impl DerefMut for VertexId {
         &mut self.0
     }
 }
+
+// Serde of the values that can be infinite, e.g. open ranges and bounds. JSON has
+// no infinity, they are written as the strings "inf" and "-inf" (NaN as "nan"),
+// the finite values as numbers
+mod float {
+    use kurbo::Rect;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        match *value {
+            value if value.is_finite() => serializer.serialize_f64(value),
+            value if value.is_nan() => serializer.serialize_str("nan"),
+            value if value > 0. => serializer.serialize_str("inf"),
+            _ => serializer.serialize_str("-inf"),
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Number(f64),
+        Text(String),
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(value) => Ok(value),
+            Repr::Text(text) => match text.as_str() {
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                "nan" => Ok(f64::NAN),
+                _ => Err(de::Error::invalid_value(
+                    de::Unexpected::Str(&text),
+                    &"a number, \"inf\", \"-inf\" or \"nan\"",
+                )),
+            },
+        }
+    }
+
+    // The vertex bounds, with the fields of kurbo::Rect
+    pub mod bounds {
+        use super::*;
+
+        #[derive(Serialize, Deserialize)]
+        struct Bounds {
+            #[serde(with = "super")]
+            x0: f64,
+            #[serde(with = "super")]
+            y0: f64,
+            #[serde(with = "super")]
+            x1: f64,
+            #[serde(with = "super")]
+            y1: f64,
+        }
+
+        pub fn serialize<S: Serializer>(
+            bounds: &Option<Rect>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            bounds
+                .map(|rect| Bounds {
+                    x0: rect.x0,
+                    y0: rect.y0,
+                    x1: rect.x1,
+                    y1: rect.y1,
+                })
+                .serialize(serializer)
+        }
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Rect>, D::Error> {
+            Ok(Option::<Bounds>::deserialize(deserializer)?
+                .map(|b| Rect::new(b.x0, b.y0, b.x1, b.y1)))
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    ops::Deref,
+};
+
+use crate::math::BindingId;
+
+// Expression of a parameter or of a binding value: numbers, parameters, the
+// operators + - * / ^, parentheses and the functions of FUNCTIONS
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    Param(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(String, Box<Expr>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+// Angles in radians
+const FUNCTIONS: [&str; 7] = ["sqrt", "abs", "sin", "cos", "tan", "asin", "acos"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamError {
+    Parse(String),
+    InvalidName(String),
+    UnknownParameter(String),
+    // The parameters of the cycle, the first one being repeated at the end
+    Cycle(Vec<String>),
+    // The expression of the parameter or binding evaluates to NaN or infinity
+    NotFinite(String),
+    HasDependents(Vec<String>),
+    UsedByBindings(Vec<BindingId>),
+    // The binding has no value an expression can drive
+    NoValue(BindingId),
+}
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Parse(msg) => write!(f, "parse error: {msg}"),
+            ParamError::InvalidName(name) => write!(f, "invalid parameter name {name:?}"),
+            ParamError::UnknownParameter(name) => write!(f, "unknown parameter {name}"),
+            ParamError::Cycle(names) => write!(f, "cyclic parameters {}", names.join(" -> ")),
+            ParamError::NotFinite(name) => write!(f, "{name} does not evaluate to a number"),
+            ParamError::HasDependents(names) => {
+                write!(f, "parameters {} depend on it", names.join(", "))
+            }
+            ParamError::UsedByBindings(ids) => write!(f, "bindings {ids:?} use it"),
+            ParamError::NoValue(id) => write!(f, "binding {id:?} has no value"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Num(f64),
+    Ident(String),
+    Sym(char),
+}
+
+// Token with its column (1-based, counting characters) and its byte offset
+pub struct Located {
+    pub token: Token,
+    pub column: usize,
+    pub offset: usize,
+}
+
+// Invalid number at the column
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    pub column: usize,
+    pub number: String,
+}
+
+// Numbers, names and any other character as a symbol, the lexer of the expressions
+// and of the text format of the sketches
+pub fn lex(src: &str) -> Result<Vec<Located>, LexError> {
+    let mut tokens = vec![];
+    let mut chars = src.char_indices().enumerate().peekable();
+    while let Some(&(column, (offset, c))) = chars.peek() {
+        let located = |token| Located {
+            token,
+            column: column + 1,
+            offset,
+        };
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut num = String::new();
+            while let Some(&(_, (_, c))) = chars.peek() {
+                // Exponent of the scientific notation, with its sign
+                let exp_sign = (c == '+' || c == '-') && num.ends_with(['e', 'E']);
+                if c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exp_sign {
+                    num.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let value = num.parse().map_err(|_| LexError {
+                column: column + 1,
+                number: num.clone(),
+            })?;
+            tokens.push(located(Token::Num(value)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&(_, (_, c))) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    ident.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(located(Token::Ident(ident)));
+        } else {
+            tokens.push(located(Token::Sym(c)));
+            chars.next();
+        }
+    }
+    Ok(tokens)
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, ParamError> {
+    lex(src)
+        .map_err(|e| ParamError::Parse(format!("invalid number {}", e.number)))?
+        .into_iter()
+        .map(|located| match located.token {
+            Token::Sym(c) if !"+-*/^(),".contains(c) => {
+                Err(ParamError::Parse(format!("unexpected character {c:?}")))
+            }
+            token => Ok(token),
+        })
+        .collect()
+}
+
+// Recursive descent, from the lowest precedence:
+//   expr  := term (('+' | '-') term)*
+//   term  := unary (('*' | '/') unary)*
+//   unary := '-' unary | power
+//   power := atom ('^' unary)?
+//   atom  := number | name | function '(' expr ')' | '(' expr ')'
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn eat(&mut self, sym: char) -> bool {
+        if self.peek() == Some(&Token::Sym(sym)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, sym: char) -> Result<(), ParamError> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            Err(ParamError::Parse(format!("expected {sym:?}")))
+        }
+    }
+    fn end(&self) -> Result<(), ParamError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(ParamError::Parse(format!("unexpected {token:?}"))),
+        }
+    }
+    fn expr(&mut self) -> Result<Expr, ParamError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+    fn term(&mut self) -> Result<Expr, ParamError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+    fn unary(&mut self) -> Result<Expr, ParamError> {
+        if self.eat('-') {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+    fn power(&mut self) -> Result<Expr, ParamError> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            // Right associative: 2^3^2 is 2^(3^2)
+            Ok(Expr::Binary(
+                Op::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+    fn atom(&mut self) -> Result<Expr, ParamError> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Num(value)) => Ok(Expr::Num(value)),
+            Some(Token::Ident(name)) if FUNCTIONS.contains(&name.as_str()) => {
+                self.expect('(')?;
+                let arg = self.expr()?;
+                self.expect(')')?;
+                Ok(Expr::Call(name, Box::new(arg)))
+            }
+            Some(Token::Ident(name)) => Ok(Expr::Param(name)),
+            Some(Token::Sym('(')) => {
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            Some(Token::Sym(c)) => Err(ParamError::Parse(format!("unexpected {c:?}"))),
+            None => Err(ParamError::Parse("unexpected end".to_string())),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr, ParamError> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+        let expr = parser.expr()?;
+        parser.end()?;
+        Ok(expr)
+    }
+    // The expressions of the count values of a binding, a point (x, y) for two
+    pub fn parse_values(src: &str, count: usize) -> Result<Vec<Expr>, ParamError> {
+        if count == 1 {
+            return Ok(vec![Expr::parse(src)?]);
+        }
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+        parser.expect('(')?;
+        let mut exprs = vec![parser.expr()?];
+        while exprs.len() < count {
+            parser.expect(',')?;
+            exprs.push(parser.expr()?);
+        }
+        parser.expect(')')?;
+        parser.end()?;
+        Ok(exprs)
+    }
+
+    // Names of the parameters the expression uses
+    pub fn params(&self, names: &mut BTreeSet<String>) {
+        match self {
+            Expr::Num(_) => (),
+            Expr::Param(name) => _ = names.insert(name.clone()),
+            Expr::Neg(e) | Expr::Call(_, e) => e.params(names),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.params(names);
+                rhs.params(names);
+            }
+        }
+    }
+
+    pub fn eval(&self, values: &BTreeMap<String, f64>) -> Result<f64, ParamError> {
+        Ok(match self {
+            Expr::Num(value) => *value,
+            Expr::Param(name) => *values
+                .get(name)
+                .ok_or_else(|| ParamError::UnknownParameter(name.clone()))?,
+            Expr::Neg(e) => -e.eval(values)?,
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(values)?, rhs.eval(values)?);
+                match op {
+                    Op::Add => lhs + rhs,
+                    Op::Sub => lhs - rhs,
+                    Op::Mul => lhs * rhs,
+                    Op::Div => lhs / rhs,
+                    Op::Pow => lhs.powf(rhs),
+                }
+            }
+            Expr::Call(name, arg) => {
+                let arg = arg.eval(values)?;
+                match name.as_str() {
+                    "sqrt" => arg.sqrt(),
+                    "abs" => arg.abs(),
+                    "sin" => arg.sin(),
+                    "cos" => arg.cos(),
+                    "tan" => arg.tan(),
+                    "asin" => arg.asin(),
+                    _ => arg.acos(),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    // The expression as written
+    pub source: String,
+    pub expr: Expr,
+    pub value: f64,
+    // Solved along the vertices, see Parameters::set_unknown
+    pub unknown: bool,
+}
+
+// Named parameters, each defined by an expression of the others. The values are
+// kept evaluated, in the order of the dependencies
+#[derive(Clone, Debug, Default)]
+pub struct Parameters {
+    params: BTreeMap<String, Parameter>,
+}
+impl Deref for Parameters {
+    type Target = BTreeMap<String, Parameter>;
+    fn deref(&self) -> &Self::Target {
+        &self.params
+    }
+}
+impl Parameters {
+    pub fn new() -> Parameters {
+        Parameters::default()
+    }
+
+    pub fn value(&self, name: &str) -> Option<f64> {
+        self.params.get(name).map(|param| param.value)
+    }
+    pub fn values(&self) -> BTreeMap<String, f64> {
+        self.params
+            .iter()
+            .map(|(name, param)| (name.clone(), param.value))
+            .collect()
+    }
+
+    // Define or redefine the parameter and evaluate the parameters again, a
+    // redefined unknown becomes known. On error the parameters are left unchanged
+    pub fn set(&mut self, name: &str, source: &str) -> Result<f64, ParamError> {
+        let is_ident = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !is_ident || FUNCTIONS.contains(&name) {
+            return Err(ParamError::InvalidName(name.to_string()));
+        }
+        let expr = Expr::parse(source)?;
+        let mut used = BTreeSet::new();
+        expr.params(&mut used);
+        if let Some(unknown) = used
+            .iter()
+            .find(|used| *used != name && !self.params.contains_key(*used))
+        {
+            return Err(ParamError::UnknownParameter(unknown.clone()));
+        }
+
+        let mut params = self.params.clone();
+        params.insert(
+            name.to_string(),
+            Parameter {
+                source: source.to_string(),
+                expr,
+                value: f64::NAN,
+                unknown: false,
+            },
+        );
+        let params = Parameters { params }.evaluated()?;
+        *self = params;
+        Ok(self.params[name].value)
+    }
+
+    // Remove the parameter, unless other parameters depend on it
+    pub fn remove(&mut self, name: &str) -> Result<Parameter, ParamError> {
+        let dependents: Vec<String> = self
+            .params
+            .iter()
+            .filter(|(_, param)| {
+                let mut used = BTreeSet::new();
+                param.expr.params(&mut used);
+                used.contains(name)
+            })
+            .map(|(dependent, _)| dependent.clone())
+            .collect();
+        if !dependents.is_empty() {
+            return Err(ParamError::HasDependents(dependents));
+        }
+        self.params
+            .remove(name)
+            .ok_or_else(|| ParamError::UnknownParameter(name.to_string()))
+    }
+
+    // Evaluate an expression of the parameters
+    pub fn eval(&self, expr: &Expr) -> Result<f64, ParamError> {
+        expr.eval(&self.values())
+    }
+
+    // Names sorted so that a parameter comes after the ones it uses
+    pub fn order(&self) -> Result<Vec<String>, ParamError> {
+        fn visit<'a>(
+            params: &'a BTreeMap<String, Parameter>,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut BTreeSet<&'a str>,
+            order: &mut Vec<String>,
+        ) -> Result<(), ParamError> {
+            if done.contains(name) {
+                return Ok(());
+            }
+            if let Some(start) = path.iter().position(|visited| *visited == name) {
+                let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+                cycle.push(name.to_string());
+                return Err(ParamError::Cycle(cycle));
+            }
+            let (name, param) = params
+                .get_key_value(name)
+                .ok_or_else(|| ParamError::UnknownParameter(name.to_string()))?;
+            path.push(name);
+            let mut used = BTreeSet::new();
+            param.expr.params(&mut used);
+            for dep in used.iter() {
+                let (dep, _) = params
+                    .get_key_value(dep)
+                    .ok_or_else(|| ParamError::UnknownParameter(dep.clone()))?;
+                visit(params, dep, path, done, order)?;
+            }
+            path.pop();
+            done.insert(name);
+            order.push(name.clone());
+            Ok(())
+        }
+
+        let mut order = vec![];
+        let mut done = BTreeSet::new();
+        for name in self.params.keys() {
+            visit(&self.params, name, &mut vec![], &mut done, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    // Mark the parameter as unknown: the solver looks for its value, starting from
+    // the current one, so that the bindings driven by it are satisfied
+    pub fn set_unknown(&mut self, name: &str, unknown: bool) -> Result<(), ParamError> {
+        self.params
+            .get_mut(name)
+            .ok_or_else(|| ParamError::UnknownParameter(name.to_string()))?
+            .unknown = unknown;
+        Ok(())
+    }
+    pub fn has_unknowns(&self) -> bool {
+        self.params.values().any(|param| param.unknown)
+    }
+    // Store the solved values of unknowns, the parameters depending on them are
+    // evaluated again
+    pub fn set_solved(&mut self, solved: &[(String, f64)]) -> Result<(), ParamError> {
+        let values = self.values_with(&solved.iter().cloned().collect())?;
+        values.into_iter().for_each(|(name, value)| {
+            self.params.get_mut(&name).unwrap().value = value;
+        });
+        Ok(())
+    }
+
+    // Parameters the expression depends on, directly or through other parameters
+    pub fn dependencies(&self, expr: &Expr) -> BTreeSet<String> {
+        let mut deps = BTreeSet::new();
+        let mut stack = BTreeSet::new();
+        expr.params(&mut stack);
+        while let Some(name) = stack.pop_first() {
+            if let Some(param) = self.params.get(&name) {
+                if deps.insert(name) {
+                    param.expr.params(&mut stack);
+                }
+            }
+        }
+        deps
+    }
+
+    // Values of the parameters, the overridden ones taking the given values. The
+    // unknowns keep their current value, their expression being the initial guess
+    pub fn values_with(
+        &self,
+        overrides: &BTreeMap<String, f64>,
+    ) -> Result<BTreeMap<String, f64>, ParamError> {
+        let mut values = BTreeMap::new();
+        for name in self.order()? {
+            let param = &self.params[&name];
+            let value = match overrides.get(&name) {
+                Some(value) => *value,
+                None if param.unknown && param.value.is_finite() => param.value,
+                None => param.expr.eval(&values)?,
+            };
+            if !value.is_finite() {
+                return Err(ParamError::NotFinite(name));
+            }
+            values.insert(name, value);
+        }
+        Ok(values)
+    }
+
+    fn evaluated(mut self) -> Result<Parameters, ParamError> {
+        let values = self.values_with(&BTreeMap::new())?;
+        values.into_iter().for_each(|(name, value)| {
+            self.params.get_mut(&name).unwrap().value = value;
+        });
+        Ok(self)
+    }
+}
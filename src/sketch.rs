@@ -0,0 +1,876 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+use kurbo::{Point, Rect};
+
+use crate::bindings::{DofAnalysis, Eq2DConstraints, Layout, SolveOptions};
+use crate::branches::{self, Branch, Orientation};
+use crate::inference::{self, Candidate, InferenceTolerances};
+use crate::math::*;
+use crate::params::{Expr, ParamError, Parameters};
+
+// Owns the vertices along with the bindings and shapes built on them, so the
+// solver always runs against the vertices the bindings refer to
+#[derive(Clone, Debug, Default)]
+pub struct Sketch {
+    vertices: VerticesPool,
+    bindings: BindingsPool,
+    shapes: ShapesPool,
+    // What solve_incremental keeps between the solves
+    cache: SolveCache,
+    parameters: Parameters,
+    // Expressions of the parameters driving the values of bindings, one per value,
+    // with their source
+    expressions: BTreeMap<BindingId, (String, Vec<Expr>)>,
+    changes: SolveChanges,
+}
+
+// What the last solve changed, before and after: the vertices it moved, the
+// values of the bindings measured or driven by the unknowns, and the unknowns
+#[derive(Clone, Debug, Default)]
+pub struct SolveChanges {
+    pub vertices: BTreeMap<VertexId, (Point, Point)>,
+    pub values: BTreeMap<BindingId, (Vec<f64>, Vec<f64>)>,
+    pub unknowns: BTreeMap<String, (f64, f64)>,
+}
+
+// A later change of the same target keeps the value before the first one
+fn record_change<K: Ord, T: PartialEq>(changes: &mut BTreeMap<K, (T, T)>, key: K, from: T, to: T) {
+    match changes.entry(key) {
+        Entry::Occupied(mut change) => change.get_mut().1 = to,
+        Entry::Vacant(change) => {
+            if from != to {
+                change.insert((from, to));
+            }
+        }
+    }
+}
+
+// The components with the layout of their last solve, and the vertices moved or
+// edited since, whose components are solved again
+#[derive(Clone, Debug, Default)]
+struct SolveCache {
+    // False until a successful solve, everything is solved then
+    solved: bool,
+    components: Vec<Component>,
+    // The bindings or shapes changed, the components must be found again
+    stale: bool,
+    dirty: BTreeSet<VertexId>,
+}
+
+#[derive(Clone, Debug)]
+struct Component {
+    v_ids: BTreeSet<VertexId>,
+    layout: Option<Layout>,
+}
+
+// Result of the solve of a layout, applied to the sketch by apply_solution
+struct Solution {
+    positions: Vec<(VertexId, Point)>,
+    unknowns: Vec<(String, f64)>,
+    norm: f64,
+    converged: bool,
+    layout: Layout,
+}
+
+// Why pools don't make a sketch, see Sketch::from_pools
+#[derive(Clone, Debug)]
+pub enum PoolsError {
+    Dangling(Vec<DanglingRef>),
+    Binding(BindingId, BindingError),
+    // A shape on vertices missing from the pool
+    Shape(ShapeTypeId),
+}
+impl fmt::Display for PoolsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolsError::Dangling(dangling) => {
+                let dangling: Vec<String> = dangling.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", dangling.join(", "))
+            }
+            PoolsError::Binding(id, e) => write!(f, "binding {}: {e}", **id),
+            PoolsError::Shape(id) => write!(f, "shape {} references unknown vertices", **id),
+        }
+    }
+}
+
+// What a vertex removal took away with it
+#[derive(Clone, Debug)]
+pub struct RemovedVertex {
+    pub vertex: Vertex,
+    pub bindings: Vec<Binding>,
+    pub shapes: Vec<ShapeType>,
+}
+
+impl Sketch {
+    pub fn new() -> Sketch {
+        Sketch {
+            vertices: VerticesPool::new(),
+            bindings: BindingsPool::new(),
+            shapes: ShapesPool::new(),
+            cache: SolveCache::default(),
+            parameters: Parameters::new(),
+            expressions: BTreeMap::new(),
+            changes: SolveChanges::default(),
+        }
+    }
+    // Sketch over pools built elsewhere, e.g. read from a document. The bindings
+    // and shapes must only reference vertices of the pool
+    pub fn from_pools(
+        vertices: VerticesPool,
+        shapes: ShapesPool,
+        bindings: BindingsPool,
+    ) -> Result<Sketch, PoolsError> {
+        bindings.validate(&vertices).map_err(PoolsError::Dangling)?;
+        bindings.values().try_for_each(|bind| {
+            bindings
+                .check(bind, &vertices)
+                .map_err(|e| PoolsError::Binding(bind.get_id(), e))
+        })?;
+        if let Some(shape) = shapes.values().find(|shape| {
+            shape
+                .get_v_ids()
+                .iter()
+                .any(|v_id| !vertices.contains_key(v_id))
+        }) {
+            return Err(PoolsError::Shape(shape.get_id()));
+        }
+        Ok(Sketch {
+            vertices,
+            bindings,
+            shapes,
+            ..Sketch::new()
+        })
+    }
+
+    pub fn vertices(&self) -> &VerticesPool {
+        &self.vertices
+    }
+    pub fn bindings(&self) -> &BindingsPool {
+        &self.bindings
+    }
+    pub fn shapes(&self) -> &ShapesPool {
+        &self.shapes
+    }
+    pub fn vertex(&self, v_id: &VertexId) -> Option<&Vertex> {
+        self.vertices.get(v_id)
+    }
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+    // Source of the expression driving the binding value
+    pub fn solve_changes(&self) -> &SolveChanges {
+        &self.changes
+    }
+    pub fn binding_expr(&self, id: &BindingId) -> Option<&str> {
+        self.expressions.get(id).map(|(source, _)| source.as_str())
+    }
+    pub fn binding_exprs(&self) -> impl Iterator<Item = (&BindingId, &str)> {
+        self.expressions
+            .iter()
+            .map(|(id, (source, _))| (id, source.as_str()))
+    }
+
+    pub fn add_vertex(&mut self, pt: Point) -> Vertex {
+        self.vertices.add(pt)
+    }
+    // Keep the vertex within bounds when solving, None removes the bounds.
+    // Return false if there is no such vertex
+    pub fn set_vertex_bounds(&mut self, v_id: &VertexId, bounds: Option<Rect>) -> bool {
+        if !self.vertices.set_bounds(v_id, bounds) {
+            return false;
+        }
+        self.restructure([*v_id]);
+        true
+    }
+    // Drag the vertex, return false if there is no such vertex
+    pub fn move_vertex(&mut self, v_id: &VertexId, pt: Point) -> bool {
+        if self.vertices.set_pt(v_id, pt).is_none() {
+            return false;
+        }
+        self.cache.dirty.insert(*v_id);
+        true
+    }
+    pub fn add_line(&mut self, va: &Vertex, vb: &Vertex) -> LineShape {
+        self.restructure([va.id, vb.id]);
+        self.shapes.add_line(va, vb)
+    }
+
+    pub fn add_bind_fixed(&mut self, v: &Vertex) -> Result<BindFixed, BindingError> {
+        let [v] = self.known([v])?;
+        self.bindings.add_bind_fixed(&v)
+    }
+    pub fn add_bind_fixed_x(&mut self, v: &Vertex) -> Result<BindFixedX, BindingError> {
+        let [v] = self.known([v])?;
+        self.bindings.add_bind_fixed_x(&v)
+    }
+    pub fn add_bind_fixed_y(&mut self, v: &Vertex) -> Result<BindFixedY, BindingError> {
+        let [v] = self.known([v])?;
+        self.bindings.add_bind_fixed_y(&v)
+    }
+    pub fn add_bind_vertical(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+    ) -> Result<BindVertical, BindingError> {
+        let [va, vb] = self.known([seg.0, seg.1])?;
+        self.bindings.add_bind_vertical((&va, &vb))
+    }
+    pub fn add_bind_horizontal(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+    ) -> Result<BindHorizontal, BindingError> {
+        let [va, vb] = self.known([seg.0, seg.1])?;
+        self.bindings.add_bind_horizontal((&va, &vb))
+    }
+    pub fn add_bind_parallel(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+    ) -> Result<BindParallel, BindingError> {
+        let [l1va, l1vb, l2va, l2vb] = self.known([seg1.0, seg1.1, seg2.0, seg2.1])?;
+        self.bindings
+            .add_bind_parallel((&l1va, &l1vb), (&l2va, &l2vb))
+    }
+    pub fn add_bind_distance(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+    ) -> Result<BindDistance, BindingError> {
+        let [va, vb] = self.known([seg.0, seg.1])?;
+        self.bindings.add_bind_distance((&va, &vb))
+    }
+    pub fn add_bind_perpendicular(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+    ) -> Result<BindPerpendicular, BindingError> {
+        let [l1va, l1vb, l2va, l2vb] = self.known([seg1.0, seg1.1, seg2.0, seg2.1])?;
+        self.bindings
+            .add_bind_perpendicular((&l1va, &l1vb), (&l2va, &l2vb))
+    }
+    pub fn add_bind_equal_length(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+    ) -> Result<BindEqualLength, BindingError> {
+        let [l1va, l1vb, l2va, l2vb] = self.known([seg1.0, seg1.1, seg2.0, seg2.1])?;
+        self.bindings
+            .add_bind_equal_length((&l1va, &l1vb), (&l2va, &l2vb))
+    }
+    pub fn add_bind_coincident(
+        &mut self,
+        va: &Vertex,
+        vb: &Vertex,
+    ) -> Result<BindCoincident, BindingError> {
+        let [va, vb] = self.known([va, vb])?;
+        self.bindings.add_bind_coincident(&va, &vb)
+    }
+    pub fn add_bind_distance_range(
+        &mut self,
+        seg: (&Vertex, &Vertex),
+        min_value: f64,
+        max_value: f64,
+    ) -> Result<BindDistanceRange, BindingError> {
+        let [va, vb] = self.known([seg.0, seg.1])?;
+        self.bindings
+            .add_bind_distance_range((&va, &vb), min_value, max_value)
+    }
+    pub fn add_bind_half_plane(
+        &mut self,
+        v: &Vertex,
+        line: (&Vertex, &Vertex),
+    ) -> Result<BindHalfPlane, BindingError> {
+        let [v, la, lb] = self.known([v, line.0, line.1])?;
+        self.bindings.add_bind_half_plane(&v, (&la, &lb))
+    }
+    pub fn add_bind_angle_range(
+        &mut self,
+        seg1: (&Vertex, &Vertex),
+        seg2: (&Vertex, &Vertex),
+        min_angle: f64,
+        max_angle: f64,
+    ) -> Result<BindAngleRange, BindingError> {
+        let [l1va, l1vb, l2va, l2vb] = self.known([seg1.0, seg1.1, seg2.0, seg2.1])?;
+        self.bindings
+            .add_bind_angle_range((&l1va, &l1vb), (&l2va, &l2vb), min_angle, max_angle)
+    }
+    pub fn add_bind_custom(
+        &mut self,
+        constraint: Arc<dyn Constraint>,
+    ) -> Result<BindCustom, BindingError> {
+        let bind = self.bindings.add_bind_custom(constraint, &self.vertices)?;
+        self.restructure(bind.constraint.v_ids());
+        Ok(bind)
+    }
+    // Add a binding built elsewhere, e.g. by the inference, under a new id
+    pub fn add_binding(&mut self, bind: Binding) -> Result<BindingId, BindingError> {
+        let id = self.bindings.add_binding(bind, &self.vertices)?;
+        let mut v_ids = BTreeSet::new();
+        self.bindings[&id].get_v_ids(&mut v_ids);
+        self.restructure(v_ids);
+        Ok(id)
+    }
+    // The vertices of a new binding must be in the sketch, the binding is checked
+    // against their current positions
+    fn known<const N: usize>(
+        &mut self,
+        vertices: [&Vertex; N],
+    ) -> Result<[Vertex; N], BindingError> {
+        if let Some(v) = vertices.iter().find(|v| !self.vertices.contains_key(&v.id)) {
+            return Err(BindingError::UnknownVertex(v.id));
+        }
+        self.restructure(vertices.iter().map(|v| v.id));
+        Ok(vertices.map(|v| self.vertices[&v.id]))
+    }
+
+    // A value or a position changed: the components of the vertices of the
+    // binding are solved again
+    fn touch(&mut self, id: &BindingId) {
+        if let Some(bind) = self.bindings.get(id) {
+            bind.get_v_ids(&mut self.cache.dirty);
+        }
+    }
+    // The bindings, shapes or bounds of the vertices changed: their components are
+    // found again, with new layouts, and solved again
+    fn restructure(&mut self, v_ids: impl IntoIterator<Item = VertexId>) {
+        let v_ids: BTreeSet<VertexId> = v_ids.into_iter().collect();
+        self.cache
+            .components
+            .retain(|component| component.v_ids.is_disjoint(&v_ids));
+        self.cache.stale = true;
+        self.cache.dirty.extend(v_ids);
+    }
+
+    // Remove the vertex, see VerticesPool::remove_vertex. With cascade the shapes
+    // built on the vertex are removed as well, without it they prevent the removal
+    pub fn remove_vertex(
+        &mut self,
+        v_id: &VertexId,
+        cascade: bool,
+    ) -> Result<RemovedVertex, RemoveError> {
+        let shape_ids = self.shapes.shapes_of(v_id);
+        if !cascade && !shape_ids.is_empty() {
+            return Err(RemoveError::UsedByShapes(shape_ids));
+        }
+        let (vertex, bindings) = self
+            .vertices
+            .remove_vertex(v_id, &mut self.bindings, cascade)?;
+        bindings.iter().for_each(|bind| {
+            self.expressions.remove(&bind.get_id());
+        });
+        let shapes: Vec<ShapeType> = shape_ids
+            .iter()
+            .filter_map(|id| self.shapes.remove_shape(id))
+            .collect();
+        let mut v_ids = BTreeSet::from([*v_id]);
+        bindings.iter().for_each(|bind| bind.get_v_ids(&mut v_ids));
+        shapes
+            .iter()
+            .for_each(|shape| v_ids.extend(shape.get_v_ids()));
+        self.restructure(v_ids);
+        Ok(RemovedVertex {
+            vertex,
+            bindings,
+            shapes,
+        })
+    }
+    // Return false if there is no such binding
+    pub fn set_strength(&mut self, id: &BindingId, strength: Strength) -> bool {
+        match self.bindings.get_mut(id) {
+            Some(bind) => {
+                bind.props_mut().strength = strength;
+                self.touch(id);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn remove_binding(&mut self, id: &BindingId) -> Option<Binding> {
+        self.expressions.remove(id);
+        let bind = self.bindings.remove_binding(id)?;
+        let mut v_ids = BTreeSet::new();
+        bind.get_v_ids(&mut v_ids);
+        self.restructure(v_ids);
+        Some(bind)
+    }
+    // Change the value of a distance or a fixed x or y binding, return false if
+    // the binding has no value
+    pub fn set_binding_value(&mut self, id: &BindingId, value: f64) -> bool {
+        let set = self
+            .bindings
+            .get_mut(id)
+            .is_some_and(|bind| bind.set_value(value));
+        if set {
+            self.touch(id);
+        }
+        set
+    }
+
+    // Put back a removed vertex, shape or binding under its id, which the pools
+    // never give again. The vertices of the shape or binding must be in the sketch
+    pub fn restore_vertex(&mut self, vertex: Vertex) -> Result<(), String> {
+        if !self.vertices.restore(vertex) {
+            return Err(format!("unknown vertex {}", *vertex.id));
+        }
+        self.restructure([vertex.id]);
+        Ok(())
+    }
+    pub fn restore_shape(&mut self, shape: ShapeType) -> Result<(), String> {
+        if let Some(v_id) = shape
+            .get_v_ids()
+            .iter()
+            .find(|v_id| !self.vertices.contains_key(v_id))
+        {
+            return Err(format!("unknown vertex {}", **v_id));
+        }
+        let (id, v_ids) = (shape.get_id(), shape.get_v_ids());
+        if !self.shapes.restore(shape) {
+            return Err(format!("unknown shape {}", *id));
+        }
+        self.restructure(v_ids);
+        Ok(())
+    }
+    pub fn restore_binding(&mut self, bind: Binding) -> Result<(), BindingError> {
+        let mut v_ids = BTreeSet::new();
+        bind.get_v_ids(&mut v_ids);
+        if let Some(v_id) = v_ids.iter().find(|v_id| !self.vertices.contains_key(v_id)) {
+            return Err(BindingError::UnknownVertex(*v_id));
+        }
+        let id = bind.get_id();
+        if !self.bindings.restore(bind) {
+            return Err(BindingError::UnknownBinding(id));
+        }
+        self.restructure(v_ids);
+        Ok(())
+    }
+
+    // Define or redefine a parameter, the bindings driven by the parameters are
+    // updated, solve to update the vertices. On error nothing changes
+    pub fn set_parameter(&mut self, name: &str, source: &str) -> Result<f64, ParamError> {
+        let mut parameters = self.parameters.clone();
+        let value = parameters.set(name, source)?;
+        let values = self.binding_values(&parameters)?;
+        self.parameters = parameters;
+        self.set_binding_values(values);
+        Ok(value)
+    }
+    // An unknown parameter is solved along the vertices so that the bindings it
+    // drives are satisfied, its value is the initial guess and then the solution
+    pub fn set_parameter_unknown(&mut self, name: &str, unknown: bool) -> Result<(), ParamError> {
+        self.parameters.set_unknown(name, unknown)
+    }
+    // Values of the unknowns solved elsewhere, e.g. read from a document
+    pub fn set_parameters_solved(&mut self, solved: &[(String, f64)]) -> Result<(), ParamError> {
+        self.parameters.set_solved(solved)
+    }
+    // Remove a parameter unused by other parameters and bindings
+    pub fn remove_parameter(&mut self, name: &str) -> Result<(), ParamError> {
+        let users: Vec<BindingId> = self
+            .expressions
+            .iter()
+            .filter(|(_, (_, exprs))| {
+                let mut used = BTreeSet::new();
+                exprs.iter().for_each(|expr| expr.params(&mut used));
+                used.contains(name)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if !users.is_empty() {
+            return Err(ParamError::UsedByBindings(users));
+        }
+        self.parameters.remove(name).map(|_| ())
+    }
+    // Drive the value of the binding (distance, fixed x or y) by an expression of
+    // the parameters, or the position of a fixed binding by a point (x, y) of two
+    // expressions. Return its current values
+    pub fn set_binding_expr(
+        &mut self,
+        id: &BindingId,
+        source: &str,
+    ) -> Result<Vec<f64>, ParamError> {
+        let bind = self.bindings.get_mut(id).ok_or(ParamError::NoValue(*id))?;
+        let count = bind.values().len();
+        if count == 0 {
+            return Err(ParamError::NoValue(*id));
+        }
+        let exprs = Expr::parse_values(source, count)?;
+        let values = eval_values(&self.parameters, source, &exprs)?;
+        bind.set_values(&values);
+        self.touch(id);
+        self.expressions.insert(*id, (source.to_string(), exprs));
+        Ok(values)
+    }
+    // The binding keeps its current value
+    pub fn clear_binding_expr(&mut self, id: &BindingId) -> bool {
+        self.expressions.remove(id).is_some()
+    }
+
+    fn binding_values(
+        &self,
+        parameters: &Parameters,
+    ) -> Result<Vec<(BindingId, Vec<f64>)>, ParamError> {
+        self.expressions
+            .iter()
+            .map(|(id, (source, exprs))| Ok((*id, eval_values(parameters, source, exprs)?)))
+            .collect()
+    }
+    // The values of the reference bindings are measured, not driven
+    fn set_binding_values(&mut self, values: Vec<(BindingId, Vec<f64>)>) {
+        values.into_iter().for_each(|(id, values)| {
+            if let Some(bind) = self.bindings.get_mut(&id) {
+                if !bind.get_props().reference {
+                    bind.set_values(&values);
+                    bind.get_v_ids(&mut self.cache.dirty);
+                }
+            }
+        });
+    }
+
+    // Suppress the binding without removing it, or enable it back.
+    // Return false if there is no such binding
+    pub fn set_enabled(&mut self, id: &BindingId, enabled: bool) -> bool {
+        match self.bindings.get_mut(id) {
+            Some(bind) => {
+                bind.props_mut().enabled = enabled;
+                let mut v_ids = BTreeSet::new();
+                bind.get_v_ids(&mut v_ids);
+                self.restructure(v_ids);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Toggle the binding between driving and reference (driven). A reference
+    // binding takes the measured value, a driving one the value of its expression
+    // if it has one, else it keeps the last measured value
+    pub fn set_reference(&mut self, id: &BindingId, reference: bool) -> Result<(), BindingError> {
+        let mut bind = self
+            .bindings
+            .get(id)
+            .ok_or(BindingError::UnknownBinding(*id))?
+            .clone();
+        bind.props_mut().reference = reference;
+        self.bindings.check(&bind, &self.vertices)?;
+        *self.bindings.get_mut(id).unwrap() = bind;
+        let mut v_ids = BTreeSet::new();
+        self.bindings[id].get_v_ids(&mut v_ids);
+        self.restructure(v_ids);
+        if reference {
+            self.measure_references();
+        } else if let Ok(values) = self.binding_values(&self.parameters) {
+            self.set_binding_values(values);
+        }
+        Ok(())
+    }
+    // Return the values changed, before and after
+    fn measure_references(&mut self) -> Vec<(BindingId, f64, f64)> {
+        let vertices = &self.vertices;
+        let mut measured = vec![];
+        self.bindings
+            .values_mut()
+            .filter(|bind| bind.get_props().reference)
+            .for_each(|bind| {
+                if let (Some(from), Some(to)) = (bind.value(), bind.measure(vertices)) {
+                    bind.set_value(to);
+                    measured.push((bind.get_id(), from, to));
+                }
+            });
+        measured
+    }
+    fn measure_solved(&mut self) {
+        self.measure_references()
+            .into_iter()
+            .for_each(|(id, from, to)| {
+                record_change(&mut self.changes.values, id, vec![from], vec![to])
+            });
+    }
+    pub fn remove_shape(&mut self, id: &ShapeTypeId) -> Option<ShapeType> {
+        let shape = self.shapes.remove_shape(id)?;
+        self.restructure(shape.get_v_ids());
+        Some(shape)
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<DanglingRef>> {
+        self.bindings.validate(&self.vertices)
+    }
+
+    // Solve the bindings and move the vertices to the solution. The vertices
+    // are moved even when the solver did not converge.
+    pub fn solve(&mut self, opts: &SolveOptions) -> Result<(), String> {
+        self.changes = SolveChanges::default();
+        let guarded = opts.preserve_orientation.then(|| self.guarded());
+        let bindings = guarded.as_ref().unwrap_or(&self.bindings);
+        let bounded = self
+            .vertices
+            .values()
+            .filter(|v| v.bounds.is_some())
+            .map(|v| v.id);
+        let layout = Layout::new(bindings.values(), bounded);
+        let solved = self
+            .solve_layout(bindings, layout, opts)
+            .and_then(|solution| self.apply_solution(solution).map(|_| ()));
+        self.measure_solved();
+        self.cache.solved = solved.is_ok();
+        if solved.is_ok() {
+            self.cache.dirty.clear();
+        }
+        solved
+    }
+
+    // Solve only the components (vertices linked by bindings or line shapes) with
+    // a vertex moved or a binding added, removed or changed since the last
+    // successful solve, everything when there is none. The other components are
+    // already solved and keep their positions. Each component keeps the layout of
+    // its system between the solves, until its bindings or bounds change
+    pub fn solve_incremental(&mut self, opts: &SolveOptions) -> Result<(), String> {
+        // The unknown parameters couple the components
+        if self.parameters.has_unknowns() {
+            return self.solve(opts);
+        }
+        self.changes = SolveChanges::default();
+        if self.cache.stale || !self.cache.solved {
+            self.find_components();
+        }
+        // The guards get new ids at each solve, their layouts aren't kept
+        let guarded = opts.preserve_orientation.then(|| self.guarded());
+        let mut components = std::mem::take(&mut self.cache.components);
+        let mut solved = Ok(());
+        for component in components.iter_mut() {
+            if component.v_ids.is_disjoint(&self.cache.dirty) {
+                continue;
+            }
+            let bindings = guarded.as_ref().unwrap_or(&self.bindings);
+            let layout = match component.layout.take() {
+                Some(layout) if guarded.is_none() => layout,
+                _ => self.component_layout(bindings, &component.v_ids),
+            };
+            let result = self
+                .solve_layout(bindings, layout, opts)
+                .and_then(|solution| self.apply_solution(solution));
+            match result {
+                Ok(layout) => {
+                    if guarded.is_none() {
+                        component.layout = Some(layout);
+                    }
+                    let dirty = &mut self.cache.dirty;
+                    component
+                        .v_ids
+                        .iter()
+                        .for_each(|v_id| _ = dirty.remove(v_id));
+                }
+                Err(e) => solved = solved.and(Err(e)),
+            }
+        }
+        self.cache.components = components;
+        self.measure_solved();
+        solved
+    }
+
+    // Find the components again, keeping the layouts of the ones left as they
+    // were. Before the first solve all of them are solved
+    fn find_components(&mut self) {
+        let mut old = std::mem::take(&mut self.cache.components);
+        self.cache.components = components(&self.vertices, &self.bindings, &self.shapes)
+            .into_iter()
+            .map(|v_ids| {
+                let layout = old
+                    .iter_mut()
+                    .find(|component| component.v_ids == v_ids)
+                    .and_then(|component| component.layout.take());
+                Component { v_ids, layout }
+            })
+            .collect();
+        if !self.cache.solved {
+            let dirty = &mut self.cache.dirty;
+            self.cache
+                .components
+                .iter()
+                .for_each(|component| dirty.extend(&component.v_ids));
+            self.cache.solved = true;
+        }
+        self.cache.stale = false;
+    }
+
+    // Layout of the bindings on the vertices of the component and of its bounded
+    // vertices
+    fn component_layout(&self, bindings: &BindingsPool, v_ids: &BTreeSet<VertexId>) -> Layout {
+        let in_component = |bind: &&Binding| {
+            let mut bind_v_ids = BTreeSet::new();
+            bind.get_v_ids(&mut bind_v_ids);
+            !bind_v_ids.is_disjoint(v_ids)
+        };
+        let bounded = v_ids
+            .iter()
+            .filter(|v_id| self.vertices[v_id].bounds.is_some())
+            .copied();
+        Layout::new(bindings.values().filter(in_component), bounded)
+    }
+
+    // Copy of the bindings with a guard keeping the sign of each orientation
+    fn guarded(&self) -> BindingsPool {
+        let guards: Vec<(Orientation, f64)> = self
+            .orientations()
+            .into_iter()
+            .map(|orientation| (orientation, orientation.sign(&self.vertices).unwrap()))
+            .collect();
+        branches::guarded(&self.bindings, &self.vertices, &guards)
+    }
+
+    fn solve_layout(
+        &self,
+        bindings: &BindingsPool,
+        layout: Layout,
+        opts: &SolveOptions,
+    ) -> Result<Solution, String> {
+        let mut cst = Eq2DConstraints::with_layout(bindings, &self.vertices, layout);
+        self.set_unknowns(&mut cst)?;
+        let (vals, norm) = cst.solve(opts)?;
+        Ok(Solution {
+            positions: cst.solution(&vals),
+            unknowns: cst.solved_unknowns(&vals),
+            norm,
+            converged: norm <= opts.tolerance,
+            layout: cst.into_layout(),
+        })
+    }
+
+    // Move the vertices to the solution and set the unknowns, return the layout
+    // when the solver converged
+    fn apply_solution(&mut self, solution: Solution) -> Result<Layout, String> {
+        solution.positions.iter().for_each(|(v_id, pt)| {
+            let from = self.vertices.set_pt(v_id, *pt).unwrap();
+            record_change(&mut self.changes.vertices, *v_id, from, *pt);
+        });
+        if !solution.unknowns.is_empty() {
+            solution.unknowns.iter().for_each(|(name, to)| {
+                if let Some(from) = self.parameters.value(name) {
+                    record_change(&mut self.changes.unknowns, name.clone(), from, *to);
+                }
+            });
+            self.parameters
+                .set_solved(&solution.unknowns)
+                .map_err(|e| e.to_string())?;
+            let values = self
+                .binding_values(&self.parameters)
+                .map_err(|e| e.to_string())?;
+            values.iter().for_each(|(id, to)| {
+                let bind = &self.bindings[id];
+                if !bind.get_props().reference {
+                    record_change(&mut self.changes.values, *id, bind.values(), to.clone());
+                }
+            });
+            self.set_binding_values(values);
+        }
+
+        if solution.converged {
+            Ok(solution.layout)
+        } else {
+            Err(format!(
+                "did not converge, residuals norm {:e}",
+                solution.norm
+            ))
+        }
+    }
+
+    // The unknown parameters are variables too, as when solving
+    pub fn dof_analysis(&self) -> Result<DofAnalysis, String> {
+        let mut cst = Eq2DConstraints::new(&self.bindings, &self.vertices)?;
+        self.set_unknowns(&mut cst)?;
+        Ok(cst.dof_analysis(&cst.initial()))
+    }
+
+    // Add the unknown parameters driving the bindings to the variables
+    fn set_unknowns(&self, cst: &mut Eq2DConstraints) -> Result<(), String> {
+        if !self.parameters.has_unknowns() {
+            return Ok(());
+        }
+        let expressions = self
+            .expressions
+            .iter()
+            .map(|(id, (_, exprs))| (*id, exprs.clone()))
+            .collect();
+        cst.set_unknowns(&self.parameters, &expressions)
+    }
+
+    pub fn orientations(&self) -> Vec<Orientation> {
+        branches::orientations(&self.vertices, &self.shapes, &self.bindings)
+    }
+    // The solutions of the bindings for each combination of orientations, the
+    // vertices don't move, see set_branch
+    pub fn branches(&self, opts: &SolveOptions) -> Result<Vec<Branch>, String> {
+        branches::enumerate_branches(&self.vertices, &self.shapes, &self.bindings, opts)
+    }
+    // Move the vertices to the positions of the branch
+    pub fn set_branch(&mut self, branch: &Branch) {
+        branch.positions.iter().for_each(|(v_id, pt)| {
+            if self.vertices.set_pt(v_id, *pt).is_some() {
+                self.cache.dirty.insert(*v_id);
+            }
+        });
+    }
+
+    pub fn infer_bindings(&self, tol: &InferenceTolerances) -> Result<Vec<Candidate>, String> {
+        inference::infer_bindings(&self.vertices, &self.shapes, &self.bindings, tol)
+    }
+}
+
+fn eval_values(
+    parameters: &Parameters,
+    source: &str,
+    exprs: &[Expr],
+) -> Result<Vec<f64>, ParamError> {
+    exprs
+        .iter()
+        .map(|expr| match parameters.eval(expr)? {
+            value if value.is_finite() => Ok(value),
+            _ => Err(ParamError::NotFinite(source.to_string())),
+        })
+        .collect()
+}
+
+// Connected components of the vertices that are solved: linked by the bindings
+// and the line shapes, the bounded vertices being solved on their own
+fn components(
+    v_pool: &VerticesPool,
+    bindings_pool: &BindingsPool,
+    shapes_pool: &ShapesPool,
+) -> Vec<BTreeSet<VertexId>> {
+    let mut groups: Vec<BTreeSet<VertexId>> = bindings_pool
+        .values()
+        .map(|bind| {
+            let mut v_ids = BTreeSet::new();
+            bind.get_v_ids(&mut v_ids);
+            v_ids
+        })
+        .chain(
+            shapes_pool
+                .values()
+                .map(|shape| shape.get_v_ids().into_iter().collect()),
+        )
+        .chain(
+            v_pool
+                .values()
+                .filter(|v| v.bounds.is_some())
+                .map(|v| BTreeSet::from([v.id])),
+        )
+        .filter(|v_ids| !v_ids.is_empty())
+        .collect();
+    let mut components: Vec<BTreeSet<VertexId>> = vec![];
+    while let Some(mut component) = groups.pop() {
+        // Absorb the groups sharing a vertex until none is left
+        loop {
+            let (linked, others): (Vec<_>, Vec<_>) = groups
+                .into_iter()
+                .partition(|group| !group.is_disjoint(&component));
+            groups = others;
+            if linked.is_empty() {
+                break;
+            }
+            linked.into_iter().for_each(|group| component.extend(group));
+        }
+        components.push(component);
+    }
+    components
+}
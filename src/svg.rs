@@ -0,0 +1,389 @@
+// SVG drawing of a sketch: the shapes, the vertices and a glyph per binding, in
+// the sketch coordinates (y down, like the canvas). The distance and angle
+// ranges, the half planes and the custom bindings have no glyph and draw
+// nothing. SVG artwork is imported the other way, as line shapes
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use kurbo::{Affine, BezPath, Circle, Ellipse, Point, Rect, Shape, Vec2};
+
+use crate::import::{ImportOptions, Importer};
+use crate::math::*;
+use crate::sketch::Sketch;
+
+#[derive(Copy, Clone, Debug)]
+pub struct SvgOptions {
+    // Size of the glyphs and of the vertices, relative to the diagonal of the
+    // drawing when None
+    pub glyph_size: Option<f64>,
+    // Draw the glyphs of the bindings, see draw_binding
+    pub annotate: bool,
+    // Accuracy of the paths of the shapes
+    pub tolerance: f64,
+}
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            glyph_size: None,
+            annotate: true,
+            tolerance: 0.1,
+        }
+    }
+}
+
+const SHAPE_COLOR: &str = "#222";
+const GLYPH_COLOR: &str = "#1565c0";
+// Reference bindings are measures, drawn apart from the driving ones
+const REFERENCE_COLOR: &str = "#777";
+
+// Builds the elements, with the sizes derived from the glyph size
+struct Drawing {
+    glyph: f64,
+    out: String,
+}
+impl Drawing {
+    fn line(&mut self, p0: Point, p1: Point, color: &str) {
+        _ = writeln!(
+            self.out,
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{color}" stroke-width="{}"/>"#,
+            p0.x,
+            p0.y,
+            p1.x,
+            p1.y,
+            self.glyph / 8.
+        );
+    }
+    fn text(&mut self, pt: Point, text: &str, color: &str) {
+        _ = writeln!(
+            self.out,
+            r#"<text x="{}" y="{}" fill="{color}" font-size="{}" font-family="sans-serif" text-anchor="middle" dominant-baseline="middle">{text}</text>"#,
+            pt.x,
+            pt.y,
+            self.glyph * 1.2
+        );
+    }
+    fn circle(&mut self, center: Point, radius: f64, fill: &str, stroke: &str) {
+        _ = writeln!(
+            self.out,
+            r#"<circle cx="{}" cy="{}" r="{radius}" fill="{fill}" stroke="{stroke}" stroke-width="{}"/>"#,
+            center.x,
+            center.y,
+            self.glyph / 8.
+        );
+    }
+
+    // Triangle under the vertex standing on a hatched ground, with an optional
+    // label of the fixed coordinate
+    fn anchor(&mut self, pt: Point, label: Option<&str>, color: &str) {
+        let g = self.glyph;
+        let (left, right) = (pt + Vec2::new(-g * 0.6, g), pt + Vec2::new(g * 0.6, g));
+        self.line(pt, left, color);
+        self.line(pt, right, color);
+        self.line(left, right, color);
+        (0..4).for_each(|idx| {
+            let top = left + Vec2::new(g * 1.2 * (idx as f64 + 0.5) / 4., 0.);
+            self.line(top, top + Vec2::new(-g * 0.3, g * 0.3), color);
+        });
+        if let Some(label) = label {
+            self.text(pt + Vec2::new(g * 1.4, g * 0.6), label, color);
+        }
+    }
+
+    // Direction and left normal of the segment, None when it is degenerate
+    fn frame(pa: Point, pb: Point) -> Option<(Vec2, Vec2)> {
+        let d = pb - pa;
+        if d.hypot() < 1e-12 {
+            return None;
+        }
+        let u = d.normalize();
+        Some((u, Vec2::new(u.y, -u.x)))
+    }
+
+    // Ticks across the middle of the segment, slanted for the parallel marks
+    fn ticks(&mut self, pa: Point, pb: Point, count: usize, slant: f64, color: &str) {
+        let Some((u, n)) = Drawing::frame(pa, pb) else {
+            return;
+        };
+        let g = self.glyph;
+        let mid = pa.midpoint(pb);
+        let t = (n + u * slant).normalize() * g * 0.5;
+        (0..count).for_each(|idx| {
+            let center = mid + u * g * 0.4 * (idx as f64 - (count - 1) as f64 / 2.);
+            self.line(center - t, center + t, color);
+        });
+    }
+
+    // Label next to the middle of the segment, on its left or right side
+    fn label(&mut self, pa: Point, pb: Point, text: &str, left: bool, color: &str) {
+        if let Some((_, n)) = Drawing::frame(pa, pb) {
+            let side = if left { 1.2 } else { -1.2 };
+            self.text(pa.midpoint(pb) + n * self.glyph * side, text, color);
+        }
+    }
+
+    // Dimension line parallel to the segment with its extension lines, arrows and
+    // the value
+    fn dimension(&mut self, pa: Point, pb: Point, value: &str, color: &str) {
+        let Some((u, n)) = Drawing::frame(pa, pb) else {
+            return;
+        };
+        let g = self.glyph;
+        let offset = n * g * 2.;
+        let (da, db) = (pa + offset, pb + offset);
+        self.line(pa + n * g * 0.3, da + n * g * 0.4, color);
+        self.line(pb + n * g * 0.3, db + n * g * 0.4, color);
+        self.line(da, db, color);
+        for (tip, dir) in [(da, u), (db, -u)] {
+            let back = tip + dir * g * 0.6;
+            self.line(tip, back + n * g * 0.25, color);
+            self.line(tip, back - n * g * 0.25, color);
+        }
+        self.text(da.midpoint(db) + n * g * 0.8, value, color);
+    }
+}
+
+pub fn export(sketch: &Sketch, opts: &SvgOptions) -> String {
+    let vertices = sketch.vertices();
+    let mut bbox: Option<Rect> = None;
+    vertices.values().for_each(|v| {
+        bbox = Some(match bbox {
+            Some(bbox) => bbox.union_pt(v.pt),
+            None => Rect::from_points(v.pt, v.pt),
+        })
+    });
+    let bbox = bbox.unwrap_or(Rect::new(0., 0., 1., 1.));
+    let diagonal = bbox.width().hypot(bbox.height()).max(1e-9);
+    let glyph = opts.glyph_size.unwrap_or(diagonal / 50.);
+    let margin = glyph * 5.;
+    let view = bbox.inflate(margin, margin);
+
+    let mut drawing = Drawing {
+        glyph,
+        out: String::new(),
+    };
+    _ = writeln!(
+        drawing.out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        view.x0,
+        view.y0,
+        view.width(),
+        view.height()
+    );
+
+    drawing.out.push_str("<g id=\"shapes\">\n");
+    sketch.shapes().values().for_each(|shape| {
+        let path = shape.get_bez_path(opts.tolerance, vertices);
+        _ = writeln!(
+            drawing.out,
+            r#"<path d="{}" fill="none" stroke="{SHAPE_COLOR}" stroke-width="{}"/>"#,
+            path.to_svg(),
+            glyph / 5.
+        );
+    });
+    drawing.out.push_str("</g>\n");
+
+    if opts.annotate {
+        drawing.out.push_str("<g id=\"bindings\">\n");
+        sketch
+            .bindings()
+            .values()
+            .for_each(|bind| draw_binding(&mut drawing, bind, vertices));
+        drawing.out.push_str("</g>\n");
+    }
+
+    drawing.out.push_str("<g id=\"vertices\">\n");
+    vertices.values().for_each(|v| {
+        drawing.circle(v.pt, glyph * 0.3, "white", SHAPE_COLOR);
+    });
+    drawing.out.push_str("</g>\n</svg>\n");
+    drawing.out
+}
+
+// The ranges, half planes and custom bindings have no glyph, they are
+// inequalities or arbitrary functions with nothing to point at
+fn draw_binding(drawing: &mut Drawing, bind: &Binding, vertices: &VerticesPool) {
+    let mut v_ids = BTreeSet::new();
+    bind.get_v_ids(&mut v_ids);
+    if v_ids.iter().any(|v_id| !vertices.contains_key(v_id)) {
+        return;
+    }
+    let pt = |v_id: &VertexId| vertices[v_id].pt;
+    let props = bind.get_props();
+    let color = if props.reference {
+        REFERENCE_COLOR
+    } else {
+        GLYPH_COLOR
+    };
+    if !props.enabled {
+        drawing.out.push_str("<g opacity=\"0.3\">\n");
+    }
+    match bind {
+        Binding::Fixed(b) => drawing.anchor(pt(&b.v_id), None, color),
+        Binding::FixedX(b) => drawing.anchor(pt(&b.v_id), Some("x"), color),
+        Binding::FixedY(b) => drawing.anchor(pt(&b.v_id), Some("y"), color),
+        Binding::Vertical(b) => drawing.label(pt(&b.va_id), pt(&b.vb_id), "V", true, color),
+        Binding::Horizontal(b) => drawing.label(pt(&b.va_id), pt(&b.vb_id), "H", true, color),
+        Binding::Parallel(b) => {
+            drawing.ticks(pt(&b.l1va_id), pt(&b.l1vb_id), 2, 0.6, color);
+            drawing.ticks(pt(&b.l2va_id), pt(&b.l2vb_id), 2, 0.6, color);
+        }
+        // On the other side than the vertical and horizontal labels
+        Binding::Perpendicular(b) => {
+            drawing.label(pt(&b.l2va_id), pt(&b.l2vb_id), "\u{22a5}", false, color)
+        }
+        Binding::EqualLength(b) => {
+            drawing.ticks(pt(&b.l1va_id), pt(&b.l1vb_id), 1, 0., color);
+            drawing.ticks(pt(&b.l2va_id), pt(&b.l2vb_id), 1, 0., color);
+        }
+        Binding::Distance(b) => {
+            // Reference dimensions are written in parentheses, as in drawings
+            let value = format!("{:.2}", b.sq_distance_value.sqrt());
+            let value = if props.reference {
+                format!("({value})")
+            } else {
+                value
+            };
+            drawing.dimension(pt(&b.va_id), pt(&b.vb_id), &value, color);
+        }
+        Binding::Coincident(b) => {
+            let radius = drawing.glyph * 0.6;
+            drawing.circle(pt(&b.va_id), radius, "none", color);
+        }
+        Binding::DistanceRange(_)
+        | Binding::HalfPlane(_)
+        | Binding::AngleRange(_)
+        | Binding::Custom(_) => (),
+    }
+    if !props.enabled {
+        drawing.out.push_str("</g>\n");
+    }
+}
+
+fn number(node: roxmltree::Node, name: &str) -> Result<f64, String> {
+    match node.attribute(name) {
+        Some(value) => value
+            .trim()
+            .trim_end_matches("px")
+            .parse()
+            .map_err(|_| format!("invalid {name} {value:?} of a {}", node.tag_name().name())),
+        None => Ok(0.),
+    }
+}
+
+// The points of a polyline or a polygon
+fn points(node: roxmltree::Node) -> Result<Vec<Point>, String> {
+    let coords = node
+        .attribute("points")
+        .unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|coord| !coord.is_empty())
+        .map(|coord| coord.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid points of a {}: {e}", node.tag_name().name()))?;
+    Ok(coords
+        .chunks_exact(2)
+        .map(|xy| Point::new(xy[0], xy[1]))
+        .collect())
+}
+
+// The transform attribute of the node, a list of matrix, translate, scale,
+// rotate, skewX and skewY functions applied right to left
+fn transform(node: roxmltree::Node) -> Result<Affine, String> {
+    let Some(list) = node.attribute("transform") else {
+        return Ok(Affine::IDENTITY);
+    };
+    let invalid = || format!("invalid transform {list:?}");
+    let mut affine = Affine::IDENTITY;
+    for function in list.split(')').map(str::trim).filter(|f| !f.is_empty()) {
+        let (name, args) = function.split_once('(').ok_or_else(invalid)?;
+        let name = name.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        let args = args
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let next = match (name.trim(), args.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => Affine::new([a, b, c, d, e, f]),
+            ("translate", &[tx]) => Affine::translate((tx, 0.)),
+            ("translate", &[tx, ty]) => Affine::translate((tx, ty)),
+            ("scale", &[s]) => Affine::scale(s),
+            ("scale", &[sx, sy]) => Affine::scale_non_uniform(sx, sy),
+            ("rotate", &[a]) => Affine::rotate(a.to_radians()),
+            ("rotate", &[a, cx, cy]) => Affine::rotate_about(a.to_radians(), Point::new(cx, cy)),
+            ("skewX", &[a]) => Affine::skew(a.to_radians().tan(), 0.),
+            ("skewY", &[a]) => Affine::skew(0., a.to_radians().tan()),
+            _ => return Err(invalid()),
+        };
+        affine *= next;
+    }
+    Ok(affine)
+}
+
+// Sketch of the line shapes drawn by the path, line, polyline, polygon, circle,
+// ellipse and rect elements, the curves being flattened once the transforms of
+// the element and of its ancestors are applied
+pub fn import(text: &str, opts: &ImportOptions) -> Result<Sketch, String> {
+    let parsing = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let doc = roxmltree::Document::parse_with_options(text, parsing).map_err(|e| e.to_string())?;
+    let mut importer = Importer::new(opts);
+    // Definitions aren't drawn, and the glyphs and the vertices drawn by export
+    // aren't shapes
+    let skipped = |node: &roxmltree::Node| {
+        node.ancestors().any(|ancestor| {
+            matches!(
+                ancestor.tag_name().name(),
+                "defs" | "symbol" | "clipPath" | "mask"
+            ) || matches!(ancestor.attribute("id"), Some("bindings" | "vertices"))
+        })
+    };
+    for node in doc
+        .descendants()
+        .filter(|node| node.is_element() && !skipped(node))
+    {
+        let n = |name| number(node, name);
+        let affine = node
+            .ancestors()
+            .try_fold(Affine::IDENTITY, |affine, ancestor| {
+                Ok::<_, String>(transform(ancestor)? * affine)
+            })?;
+        match node.tag_name().name() {
+            "path" => {
+                let d = node.attribute("d").unwrap_or_default();
+                let path = BezPath::from_svg(d).map_err(|e| format!("invalid path: {e}"))?;
+                importer.path(&(affine * path));
+            }
+            "line" => importer.segment(
+                affine * Point::new(n("x1")?, n("y1")?),
+                affine * Point::new(n("x2")?, n("y2")?),
+            ),
+            name @ ("polyline" | "polygon") => {
+                let mut pts: Vec<_> = points(node)?.into_iter().map(|pt| affine * pt).collect();
+                if name == "polygon" && pts.len() > 2 {
+                    pts.push(pts[0]);
+                }
+                pts.windows(2)
+                    .for_each(|pair| importer.segment(pair[0], pair[1]));
+            }
+            "circle" => {
+                let circle = Circle::new((n("cx")?, n("cy")?), n("r")?);
+                importer.path(&(affine * circle.to_path(opts.tolerance)));
+            }
+            "ellipse" => {
+                let radii = Vec2::new(n("rx")?, n("ry")?);
+                let ellipse = Ellipse::new((n("cx")?, n("cy")?), radii, 0.);
+                importer.path(&(affine * ellipse.to_path(opts.tolerance)));
+            }
+            "rect" => {
+                let rect =
+                    Rect::new(0., 0., n("width")?, n("height")?) + Vec2::new(n("x")?, n("y")?);
+                importer.path(&(affine * rect.to_path(opts.tolerance)));
+            }
+            _ => (),
+        }
+    }
+    Ok(importer.sketch())
+}
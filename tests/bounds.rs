@@ -0,0 +1,38 @@
+use kurbo::{Point, Rect};
+use test_gomez::bindings::SolveOptions;
+use test_gomez::sketch::Sketch;
+
+#[test]
+fn unbound_vertex_is_clamped() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(5.5, 7.));
+    let bounds = Rect::new(5., 5., 6., 6.);
+    assert!(sketch.set_vertex_bounds(&a.id, Some(bounds)));
+    assert!(sketch.set_vertex_bounds(&b.id, Some(bounds)));
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert_eq!(sketch.vertex(&a.id).unwrap().pt, Point::new(5., 5.));
+    assert_eq!(sketch.vertex(&b.id).unwrap().pt, Point::new(5.5, 6.));
+}
+
+// The distance pulls b out of its box, the solution slides along its edge
+#[test]
+fn bound_vertex_stays_inside() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(2., 2.));
+    sketch.add_bind_fixed(&a).unwrap();
+    let length = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&length, 10.);
+    let bounds = Rect::new(0., 0., 3., 20.);
+    assert!(sketch.set_vertex_bounds(&b.id, Some(bounds)));
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let pt = sketch.vertex(&b.id).unwrap().pt;
+    // On the edge, which Rect::contains leaves out
+    assert!(
+        pt.x <= 3. && pt.x >= 0. && pt.y >= 0. && pt.y <= 20.,
+        "{pt:?}"
+    );
+    assert_eq!(pt.x, 3.);
+    assert!((pt.distance(Point::ZERO) - 10.).abs() < 1e-6, "{pt:?}");
+}
@@ -0,0 +1,95 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::math::Vertex;
+use test_gomez::sketch::Sketch;
+
+// The apex c of a triangle on the fixed base a-b, at given distances from a and b
+fn triangle(origin: Point, c: Point) -> (Sketch, Vertex) {
+    let mut sketch = Sketch::new();
+    let c = add_triangle(&mut sketch, origin, c);
+    (sketch, c)
+}
+
+fn add_triangle(sketch: &mut Sketch, origin: Point, c: Point) -> Vertex {
+    let a = sketch.add_vertex(origin);
+    let b = sketch.add_vertex(origin + (4., 0.));
+    let c = sketch.add_vertex(origin + c.to_vec2());
+    sketch.add_line(&a, &b);
+    sketch.add_line(&b, &c);
+    sketch.add_line(&c, &a);
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_fixed(&b).unwrap();
+    sketch.add_bind_distance((&a, &c)).unwrap();
+    sketch.add_bind_distance((&b, &c)).unwrap();
+    c
+}
+
+#[test]
+fn mirrored_branch_is_listed() {
+    let (mut sketch, c) = triangle(Point::ZERO, Point::new(1., 3.));
+    let before = sketch.vertex(&c.id).unwrap().pt;
+    let branches = sketch.branches(&SolveOptions::default()).unwrap();
+    assert_eq!(branches.len(), 2, "{branches:?}");
+    assert_eq!(sketch.vertex(&c.id).unwrap().pt, before);
+    let apex = |idx: usize| {
+        let branch = &branches[idx];
+        branch
+            .positions
+            .iter()
+            .find(|(v_id, _)| *v_id == c.id)
+            .unwrap()
+            .1
+    };
+    // The current branch first
+    assert!(
+        (apex(0) - Point::new(1., 3.)).hypot() < 1e-6,
+        "{:?}",
+        apex(0)
+    );
+    assert!(
+        (apex(1) - Point::new(1., -3.)).hypot() < 1e-6,
+        "{:?}",
+        apex(1)
+    );
+    assert_eq!(branches[0].signs.len(), 1);
+    assert_eq!(branches[0].signs[0], -branches[1].signs[0]);
+
+    sketch.set_branch(&branches[1]);
+    assert_eq!(sketch.vertex(&c.id).unwrap().pt, apex(1));
+}
+
+// Dragged near the base, on either side, the apex comes back on that side
+#[test]
+fn orientation_is_preserved() {
+    let opts = SolveOptions {
+        preserve_orientation: true,
+        ..SolveOptions::default()
+    };
+    for y in [0.1, -0.1] {
+        let (mut sketch, c) = triangle(Point::ZERO, Point::new(1., 3.));
+        sketch.move_vertex(&c.id, Point::new(2., y));
+        sketch.solve(&opts).unwrap();
+        let pt = sketch.vertex(&c.id).unwrap().pt;
+        assert!(
+            (pt - Point::new(1., 3. * y.signum())).hypot() < 1e-6,
+            "{pt:?}"
+        );
+    }
+}
+
+// 2^10 solves at most, past 10 orientations the branches aren't enumerated
+#[test]
+fn enumeration_is_capped() {
+    let mut sketch = Sketch::new();
+    for idx in 0..10 {
+        add_triangle(
+            &mut sketch,
+            Point::new(10. * idx as f64, 0.),
+            Point::new(1., 3.),
+        );
+    }
+    assert_eq!(sketch.orientations().len(), 10);
+    add_triangle(&mut sketch, Point::new(100., 0.), Point::new(1., 3.));
+    assert_eq!(sketch.orientations().len(), 11);
+    assert!(sketch.branches(&SolveOptions::default()).is_err());
+}
@@ -0,0 +1,88 @@
+use kurbo::Point;
+use test_gomez::dsl;
+use test_gomez::math::{BindingError, Vertex};
+use test_gomez::sketch::Sketch;
+
+fn square() -> (Sketch, [Vertex; 4]) {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(1., 0.));
+    let c = sketch.add_vertex(Point::new(1., 1.));
+    let d = sketch.add_vertex(Point::new(0., 1.));
+    (sketch, [a, b, c, d])
+}
+
+#[test]
+fn segment_bound_to_itself() {
+    let (mut sketch, [a, b, ..]) = square();
+    assert_eq!(
+        sketch
+            .add_bind_perpendicular((&a, &b), (&b, &a))
+            .unwrap_err(),
+        BindingError::SameSegment(a.id, b.id)
+    );
+    assert_eq!(
+        sketch
+            .add_bind_equal_length((&a, &b), (&a, &b))
+            .unwrap_err(),
+        BindingError::SameSegment(a.id, b.id)
+    );
+    assert_eq!(
+        sketch.add_bind_parallel((&a, &b), (&a, &b)).unwrap_err(),
+        BindingError::SameSegment(a.id, b.id)
+    );
+}
+
+#[test]
+fn added_bindings_are_checked_on_the_sketch() {
+    let (mut sketch, [a, b, c, d]) = square();
+    let mut other = sketch.clone();
+    let id = other.add_bind_parallel((&a, &b), (&d, &c)).unwrap().id;
+    let parallel = other.bindings()[&id].clone();
+
+    // The vertices of the sketch, not the copies, make the segment degenerate
+    sketch.move_vertex(&b.id, Point::ZERO);
+    assert_eq!(
+        sketch.add_binding(parallel.clone()).unwrap_err(),
+        BindingError::DegenerateParallel
+    );
+    assert_eq!(
+        sketch.add_bind_parallel((&a, &b), (&d, &c)).unwrap_err(),
+        BindingError::DegenerateParallel
+    );
+    sketch.move_vertex(&b.id, Point::new(1., 0.));
+    let id = sketch.add_binding(parallel.clone()).unwrap();
+    assert_eq!(
+        sketch.add_binding(parallel).unwrap_err(),
+        BindingError::Duplicate(id)
+    );
+}
+
+#[test]
+fn references_are_not_duplicates() {
+    let (mut sketch, [a, b, ..]) = square();
+    let driving = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    let reference = sketch.add_bind_distance((&b, &a)).unwrap_err();
+    assert_eq!(reference, BindingError::Duplicate(driving));
+
+    sketch.set_reference(&driving, true).unwrap();
+    let other = sketch.add_bind_distance((&b, &a)).unwrap().id;
+    // Driving again, it would duplicate the other one
+    assert_eq!(
+        sketch.set_reference(&driving, false),
+        Err(BindingError::Duplicate(other))
+    );
+    assert!(sketch.bindings()[&driving].get_props().reference);
+
+    let text = "\
+point a = (0, 0)
+point b = (3, 4)
+distance a b = 5
+reference distance a b
+";
+    let sketch = dsl::parse(text).unwrap();
+    assert_eq!(sketch.bindings().len(), 2);
+    let text = format!("{text}perpendicular (a, b) (b, a)\n");
+    let err = dsl::parse(&text).unwrap_err();
+    assert_eq!((err.line, err.column), (5, 1));
+}
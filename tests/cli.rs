@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const TRIANGLE: &str = "\
+point a = (0, 0)
+point b = (4, 0)
+point c = (1, 3)
+line (a, b)
+line (b, c)
+fixed a
+horizontal (a, b)
+distance a b = 5
+";
+
+// b can't be both on the horizontal and at (4, 2)
+const CONTRADICTORY: &str = "\
+point a = (0, 0)
+point b = (4, 0)
+fixed a
+fixed b = (4, 2)
+horizontal (a, b)
+";
+
+// A directory of the test for its files
+fn dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("test-gomez-{}-{test}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn sketch(dir: &Path, name: &str, text: &str) -> String {
+    let path = dir.join(name);
+    fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_test-gomez"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn solve_exit_codes() {
+    let dir = dir("exit_codes");
+    let triangle = sketch(&dir, "triangle.sketch", TRIANGLE);
+    let output = run(&["solve", &triangle]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("converged\n"), "{output:?}");
+    assert_eq!(stdout(&output).lines().count(), 5, "{output:?}");
+
+    let contradictory = sketch(&dir, "contradictory.sketch", CONTRADICTORY);
+    let output = run(&["solve", &contradictory]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("failed: "), "{output:?}");
+
+    for args in [
+        vec!["solve", "missing.sketch"],
+        vec!["solve", &triangle, "--tol", "small"],
+        vec!["solve", &triangle, "--format", "svg"],
+        vec!["solve", &triangle, "--output", "-"],
+        vec!["check", &triangle, "--format", "dxf"],
+        vec!["unknown", &triangle],
+    ] {
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(output.stdout.is_empty(), "{args:?}");
+        assert!(!output.stderr.is_empty(), "{args:?}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+// The traces don't mix with the report, and the solved sketch goes to the output
+#[test]
+fn solve_json_report() {
+    let dir = dir("json_report");
+    let triangle = sketch(&dir, "triangle.sketch", TRIANGLE);
+    let solved = dir.join("solved.json");
+    let solved = solved.to_str().unwrap();
+    let output = run(&[
+        "solve",
+        &triangle,
+        "--format",
+        "json",
+        "--verbose",
+        "--output",
+        solved,
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!output.stderr.is_empty());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["converged"], true);
+    let x = report["vertices"][1]["x"].as_f64().unwrap();
+    assert!((x - 5.).abs() < 1e-6, "{report}");
+
+    let output = run(&["check", solved, "--format", "json"]);
+    assert_eq!(output.status.code(), Some(0));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["dof"], 0);
+    assert_eq!(report["redundant"], 0);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn check_and_convert() {
+    let dir = dir("convert");
+    let triangle = sketch(&dir, "triangle.sketch", TRIANGLE);
+    let output = run(&["check", &triangle]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "variables: 4, equations: 4, rank: 4\ndof: 0, redundant: 0\n"
+    );
+
+    let json = dir.join("triangle.json");
+    let json = json.to_str().unwrap();
+    assert_eq!(run(&["convert", &triangle, json]).status.code(), Some(0));
+    // Printed the same through the JSON document
+    let output = run(&["convert", json, "-"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), stdout(&run(&["convert", &triangle, "-"])));
+    assert!(
+        stdout(&output).contains("distance p0 p1 = 5\n"),
+        "{output:?}"
+    );
+
+    let output = run(&["convert", json, "-", "--format", "dxf"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("DIMENSION"), "{output:?}");
+    assert_eq!(run(&["convert", json]).status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::document::{self, DocumentError};
+use test_gomez::math::{Binding, Constraint, VertexId};
+use test_gomez::sketch::Sketch;
+
+// The vertex on the circle of the center and radius, defined outside of the crate
+#[derive(Debug)]
+struct OnCircle {
+    v_id: VertexId,
+    center: Point,
+    radius: f64,
+}
+impl Constraint for OnCircle {
+    fn v_ids(&self) -> Vec<VertexId> {
+        vec![self.v_id]
+    }
+    fn eq_count(&self) -> usize {
+        1
+    }
+    fn residuals(&self, vals: &[f64], rx: &mut [f64]) {
+        rx[0] = Point::new(vals[0], vals[1]).distance(self.center) - self.radius;
+    }
+}
+
+fn sketch_with_custom() -> Sketch {
+    let mut sketch = Sketch::new();
+    let v = sketch.add_vertex(Point::new(1., 1.));
+    sketch.add_bind_fixed_x(&v).unwrap();
+    let on_circle = OnCircle {
+        v_id: v.id,
+        center: Point::ZERO,
+        radius: 5.,
+    };
+    sketch.add_bind_custom(Arc::new(on_circle)).unwrap();
+    sketch
+}
+
+#[test]
+fn custom_constraint_is_solved() {
+    let mut sketch = sketch_with_custom();
+    let x_id = sketch
+        .bindings()
+        .values()
+        .find(|bind| matches!(bind, Binding::FixedX(_)))
+        .unwrap()
+        .get_id();
+    sketch.set_binding_value(&x_id, 3.);
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let pt = sketch.vertices().values().next().unwrap().pt;
+    assert!((pt.x - 3.).abs() < 1e-6);
+    assert!((pt.y - 4.).abs() < 1e-6);
+}
+
+#[test]
+fn custom_binding_is_not_saved() {
+    let sketch = sketch_with_custom();
+    let id = sketch.bindings().values().last().unwrap().get_id();
+    assert_eq!(
+        document::to_json(&sketch).unwrap_err(),
+        DocumentError::CustomBinding(id)
+    );
+    let err = serde_json::to_string(sketch.bindings()).unwrap_err();
+    assert!(err.to_string().contains("can't be saved"), "{err}");
+}
@@ -0,0 +1,76 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::import::ImportOptions;
+use test_gomez::math::{Binding, BindingId};
+use test_gomez::sketch::Sketch;
+use test_gomez::{document, dsl, dxf};
+
+// b on the horizontal through the fixed a, at 10 from a but for the distance
+// being disabled
+fn sketch() -> (Sketch, BindingId) {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(3., 4.));
+    sketch.add_line(&a, &b);
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_horizontal((&a, &b)).unwrap();
+    let length = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&length, 10.);
+    assert!(sketch.set_enabled(&length, false));
+    (sketch, length)
+}
+
+fn enabled(sketch: &Sketch) -> Vec<bool> {
+    let mut enabled: Vec<_> = sketch
+        .bindings()
+        .values()
+        .map(|bind| bind.get_props().enabled)
+        .collect();
+    enabled.sort();
+    enabled
+}
+
+#[test]
+fn disabled_binding_is_skipped() {
+    let (mut sketch, length) = sketch();
+    let analysis = sketch.dof_analysis().unwrap();
+    assert_eq!((analysis.n_eqs, analysis.dof()), (3, 1), "{analysis:?}");
+    sketch.solve(&SolveOptions::default()).unwrap();
+    // Brought down on the horizontal, not stretched
+    let pt = sketch.vertices().values().nth(1).unwrap().pt;
+    assert!((pt - Point::new(3., 0.)).hypot() < 1e-6, "{pt:?}");
+
+    assert!(sketch.set_enabled(&length, true));
+    let analysis = sketch.dof_analysis().unwrap();
+    assert_eq!((analysis.n_eqs, analysis.dof()), (4, 0), "{analysis:?}");
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let pt = sketch.vertices().values().nth(1).unwrap().pt;
+    assert!((pt - Point::new(10., 0.)).hypot() < 1e-6, "{pt:?}");
+}
+
+#[test]
+fn enabled_flag_round_trips() {
+    let (sketch, _) = sketch();
+    let expected = vec![false, true, true];
+    assert_eq!(enabled(&sketch), expected);
+
+    let json = document::to_json(&sketch).unwrap();
+    assert_eq!(enabled(&document::from_json(&json).unwrap()), expected);
+
+    let text = dsl::print(&sketch).unwrap();
+    assert!(text.contains("disabled distance"), "{text}");
+    assert_eq!(enabled(&dsl::parse(&text).unwrap()), expected);
+
+    // The horizontal binding isn't written to DXF, the dimension is
+    let text = dxf::export(&sketch);
+    let imported = dxf::import(&text, &ImportOptions::default()).unwrap();
+    let disabled: Vec<_> = imported
+        .bindings()
+        .values()
+        .filter(|bind| !bind.get_props().enabled)
+        .collect();
+    assert!(
+        matches!(disabled[..], [Binding::Distance(_)]),
+        "{disabled:?}"
+    );
+}
@@ -0,0 +1,65 @@
+use test_gomez::bindings::SolveOptions;
+use test_gomez::document;
+use test_gomez::dsl;
+use test_gomez::sketch::Sketch;
+
+const TEXT: &str = "\
+param w = 4
+point a = (0, 0)
+point b = (3, 0)
+point c = (1, 2)
+bounds c = (-inf, 1) (inf, inf)
+fixed a
+horizontal (a, b)
+distance a b in [5, inf]
+distance a c = w
+angle (a, b) (a, c) in [-inf, 1]
+";
+
+fn round_trip(sketch: &Sketch) -> Sketch {
+    let json = document::to_json(sketch).unwrap();
+    document::from_json(&json).unwrap()
+}
+
+#[test]
+fn infinite_values_round_trip() {
+    let sketch = dsl::parse(TEXT).unwrap();
+    let json = document::to_json(&sketch).unwrap();
+    assert!(
+        json.contains("\"inf\"") && json.contains("\"-inf\""),
+        "{json}"
+    );
+    let loaded = document::from_json(&json).unwrap();
+    assert_eq!(dsl::print(&loaded).unwrap(), dsl::print(&sketch).unwrap());
+}
+
+#[test]
+fn loaded_sketch_solves_the_same() {
+    let opts = SolveOptions::default();
+    let mut sketch = dsl::parse(TEXT).unwrap();
+    let mut loaded = round_trip(&sketch);
+    sketch.solve(&opts).unwrap();
+    loaded.solve(&opts).unwrap();
+    assert_eq!(dsl::print(&loaded).unwrap(), dsl::print(&sketch).unwrap());
+
+    let b = sketch.vertices().values().nth(1).unwrap().pt;
+    assert!(b.x >= 5. - 1e-6, "{b:?}");
+    let c = sketch.vertices().values().nth(2).unwrap().pt;
+    assert!(c.y >= 1. - 1e-6 && (c.distance(kurbo::Point::ZERO) - 4.).abs() < 1e-6);
+
+    // The solved sketch saves and loads as solved
+    assert_eq!(
+        dsl::print(&round_trip(&sketch)).unwrap(),
+        dsl::print(&sketch).unwrap()
+    );
+}
+
+#[test]
+fn invalid_number_text_is_an_error() {
+    let sketch = dsl::parse(TEXT).unwrap();
+    let json = document::to_json(&sketch)
+        .unwrap()
+        .replace("\"inf\"", "\"infinity\"");
+    let err = document::from_json(&json).unwrap_err();
+    assert!(err.to_string().contains("infinity"), "{err}");
+}
@@ -0,0 +1,36 @@
+use test_gomez::dsl;
+
+fn error_at(text: &str) -> (usize, usize, String) {
+    let err = dsl::parse(text).unwrap_err();
+    (err.line, err.column, err.message)
+}
+
+#[test]
+fn invalid_numbers_are_located() {
+    let (line, column, message) = error_at("point a = (0, 0)\npoint b = (1.2.3, 0)\n");
+    assert_eq!((line, column), (2, 12));
+    assert_eq!(message, "invalid number 1.2.3");
+
+    // Expressions are read by the same lexer
+    let (line, column, message) = error_at("param w = 2 * 1e\n");
+    assert_eq!((line, column), (1, 15));
+    assert_eq!(message, "invalid number 1e");
+}
+
+#[test]
+fn print_reads_back() {
+    let text = "\
+param w = 3
+unknown r = 2
+point p0 = (0, 0)
+point p1 = (3, 4)
+line (p0, p1)
+fixed p0 = (w, 0)
+reference distance p0 p1 = 5
+distance p0 p1 = r * 2
+";
+    let printed = dsl::print(&dsl::parse(text).unwrap()).unwrap();
+    assert_eq!(dsl::print(&dsl::parse(&printed).unwrap()).unwrap(), printed);
+    assert!(printed.contains("param w = 3\n"), "{printed}");
+    assert!(printed.contains("unknown r = 2\n"), "{printed}");
+}
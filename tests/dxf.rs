@@ -0,0 +1,136 @@
+use kurbo::Point;
+use test_gomez::dxf;
+use test_gomez::import::ImportOptions;
+use test_gomez::math::Binding;
+use test_gomez::sketch::Sketch;
+
+// A right triangle fixed by a vertex, its hypotenuse measured
+fn triangle() -> Sketch {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 0.));
+    let c = sketch.add_vertex(Point::new(0., 3.));
+    let d = sketch.add_vertex(Point::new(8., 8.));
+    sketch.add_line(&a, &b);
+    sketch.add_line(&b, &c);
+    sketch.add_line(&c, &a);
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_fixed_y(&d).unwrap();
+    sketch.add_bind_distance((&a, &b)).unwrap();
+    let hypotenuse = sketch.add_bind_distance((&b, &c)).unwrap().id;
+    sketch.set_reference(&hypotenuse, true).unwrap();
+    let right = sketch
+        .add_bind_perpendicular((&a, &b), (&a, &c))
+        .unwrap()
+        .id;
+    assert!(sketch.set_enabled(&right, false));
+    sketch
+}
+
+#[test]
+fn bindings_round_trip() {
+    let sketch = triangle();
+    let text = dxf::export(&sketch);
+    let imported = dxf::import(&text, &ImportOptions::default()).unwrap();
+    let summary = |sketch: &Sketch| {
+        let mut bindings: Vec<_> = sketch
+            .bindings()
+            .values()
+            .map(|bind| {
+                let kind = format!("{bind:?}");
+                let kind = kind[..kind.find('(').unwrap()].to_string();
+                let values: Vec<_> = bind.values().iter().map(|v| format!("{v:.6}")).collect();
+                (kind, *bind.get_props(), values)
+            })
+            .collect();
+        bindings.sort_by(|a, b| format!("{a:?}").cmp(&format!("{b:?}")));
+        bindings
+    };
+    assert_eq!(summary(&imported), summary(&sketch));
+    let fixed = imported
+        .bindings()
+        .values()
+        .find(|bind| matches!(bind, Binding::Fixed(_)));
+    assert_eq!(fixed.unwrap().values(), vec![0., 0.]);
+}
+
+#[test]
+fn dimensions_have_blocks() {
+    let text = dxf::export(&triangle());
+    for section in ["TABLES", "BLOCKS", "ENTITIES"] {
+        assert!(
+            text.contains(&format!("SECTION\n  2\n{section}\n")),
+            "{text}"
+        );
+    }
+    // The DIMENSION entities name their blocks
+    let names: Vec<_> = text
+        .split("  0\nDIMENSION\n  8\n0\n  2\n")
+        .skip(1)
+        .map(|rest| rest.lines().next().unwrap())
+        .collect();
+    assert_eq!(names, ["*D1", "*D2", "*D3"]);
+    assert!(names
+        .iter()
+        .all(|name| text.contains(&format!("BLOCK\n  8\n0\n  2\n{name}\n"))));
+}
+
+#[test]
+fn linear_dimensions_are_read() {
+    let entity = |groups: &[(i32, &str)]| {
+        groups
+            .iter()
+            .map(|(code, value)| format!("{code}\n{value}\n"))
+            .collect::<String>()
+    };
+    let text = [
+        entity(&[(0, "SECTION"), (2, "ENTITIES")]),
+        entity(&[(0, "LINE"), (10, "0"), (20, "0"), (11, "4"), (21, "3")]),
+        // Along the line
+        entity(&[
+            (0, "DIMENSION"),
+            (70, "0"),
+            (50, "36.86989764584402"),
+            (13, "0"),
+            (23, "0"),
+            (14, "4"),
+            (24, "3"),
+            (42, "6"),
+        ]),
+        // Its horizontal span
+        entity(&[
+            (0, "DIMENSION"),
+            (70, "0"),
+            (50, "0"),
+            (13, "0"),
+            (23, "0"),
+            (14, "4"),
+            (24, "3"),
+            (42, "2"),
+        ]),
+        entity(&[(0, "ENDSEC"), (0, "EOF")]),
+    ]
+    .concat();
+    let sketch = dxf::import(&text, &ImportOptions::default()).unwrap();
+    let mut kinds: Vec<_> = sketch
+        .bindings()
+        .values()
+        .map(|bind| match bind {
+            Binding::Distance(_) => format!("distance {:?}", bind.value().unwrap()),
+            Binding::Horizontal(_) => "horizontal".to_string(),
+            Binding::Vertical(_) => "vertical".to_string(),
+            _ => panic!("{bind:?}"),
+        })
+        .collect();
+    kinds.sort();
+    assert_eq!(
+        kinds,
+        ["distance 2.0", "distance 6.0", "horizontal", "vertical"]
+    );
+    // The corner of the horizontal span
+    assert_eq!(sketch.vertices().len(), 3);
+    assert!(sketch
+        .vertices()
+        .values()
+        .any(|v| v.pt == Point::new(4., 0.)));
+}
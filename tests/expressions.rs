@@ -0,0 +1,96 @@
+use test_gomez::bindings::SolveOptions;
+use test_gomez::document;
+use test_gomez::dsl;
+use test_gomez::math::Binding;
+use test_gomez::sketch::Sketch;
+
+fn fixed_at(sketch: &Sketch) -> (f64, f64) {
+    let pt = sketch
+        .bindings()
+        .values()
+        .find_map(|bind| match bind {
+            Binding::Fixed(b) => Some(sketch.vertex(&b.v_id).unwrap().pt),
+            _ => None,
+        })
+        .unwrap();
+    (pt.x, pt.y)
+}
+
+fn assert_near((x, y): (f64, f64), (ex, ey): (f64, f64)) {
+    assert!((x - ex).abs() < 1e-6 && (y - ey).abs() < 1e-6, "({x}, {y})");
+}
+
+#[test]
+fn fixed_driven_by_parameters() {
+    let text = "param w = 10\npoint a = (0, 0)\nfixed a = (w / 2, w)\n";
+    let mut sketch = dsl::parse(text).unwrap();
+    let opts = SolveOptions::default();
+    sketch.solve(&opts).unwrap();
+    assert_near(fixed_at(&sketch), (5., 10.));
+
+    sketch.set_parameter("w", "4").unwrap();
+    sketch.solve(&opts).unwrap();
+    assert_near(fixed_at(&sketch), (2., 4.));
+
+    let printed = dsl::print(&sketch).unwrap();
+    assert!(printed.contains("fixed p0 = (w / 2, w)"), "{printed}");
+    let loaded = document::from_json(&document::to_json(&sketch).unwrap()).unwrap();
+    let id = loaded.bindings().keys().next().unwrap();
+    assert_eq!(loaded.binding_expr(id), Some("(w / 2, w)"));
+}
+
+#[test]
+fn fixed_expression_needs_a_point() {
+    let text = "param w = 10\npoint a = (0, 0)\nfixed a = w\n";
+    let err = dsl::parse(text).unwrap_err();
+    assert_eq!((err.line, err.column), (3, 11));
+}
+
+#[test]
+fn fixed_driven_by_unknown() {
+    let text = "\
+unknown u = 7
+point a = (0, 0)
+point b = (10, 0)
+fixed a = (u, 0)
+fixed b
+distance a b = 2
+";
+    let mut sketch = dsl::parse(text).unwrap();
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert!((sketch.parameters().value("u").unwrap() - 8.).abs() < 1e-6);
+    assert_near(fixed_at(&sketch), (8., 0.));
+}
+
+#[test]
+fn unknowns_are_analysed_as_variables() {
+    let text = "\
+unknown r = 5
+point a = (0, 0)
+point b = (3, 4)
+fixed a
+fixed b
+distance a b = r
+";
+    let sketch = dsl::parse(text).unwrap();
+    let analysis = sketch.dof_analysis().unwrap();
+    assert_eq!((analysis.n_vars, analysis.n_eqs), (5, 5));
+    assert_eq!((analysis.dof(), analysis.redundant()), (0, 0));
+}
+
+// u reaches 8 where h isn't defined, which is the reason the solve fails
+#[test]
+fn undefined_parameter_fails_the_solve() {
+    let text = "\
+unknown u = 7
+param h = sqrt(7.5 - u)
+point a = (0, 0)
+point b = (10, 0)
+fixed a = (u, 0)
+fixed b
+distance a b = 2
+";
+    let mut sketch = dsl::parse(text).unwrap();
+    let err = sketch.solve(&SolveOptions::default()).unwrap_err();
+    assert!(err.contains("h does not evaluate"), "{err}");
+}
@@ -0,0 +1,223 @@
+use kurbo::{Point, Rect};
+use serde_json::Value;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::document;
+use test_gomez::history::History;
+use test_gomez::math::{Strength, Vertex};
+use test_gomez::sketch::Sketch;
+
+// The document of the sketch but for the id counters, which undo leaves ahead
+fn json(history: &History) -> Value {
+    let json = document::to_json(history.sketch()).unwrap();
+    let mut json: Value = serde_json::from_str(&json).unwrap();
+    for pool in ["vertices", "shapes", "bindings"] {
+        json[pool].as_object_mut().unwrap().remove("next_id");
+    }
+    json
+}
+
+// Undo the edit back to the sketch before it, then redo it
+fn assert_undone(history: &mut History, before: &Value) {
+    let after = json(history);
+    assert_ne!(&after, before);
+    assert!(history.undo().unwrap());
+    assert_eq!(&json(history), before);
+    assert!(history.redo().unwrap());
+    assert_eq!(json(history), after);
+}
+
+fn pt(history: &History, v: &Vertex) -> Point {
+    history.sketch().vertex(&v.id).unwrap().pt
+}
+
+// The moves recorded by the steps of the history
+fn moves(history: &History) -> usize {
+    format!("{history:?}").matches("MoveVertex").count()
+}
+
+#[test]
+fn additions_are_undone() {
+    let mut history = History::new(Sketch::new());
+    let before = json(&history);
+    let a = history.add_vertex(Point::new(0., 0.));
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    let b = history.add_vertex(Point::new(4., 0.));
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    history.add_line(&a, &b);
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    history
+        .add_bind(|sketch| sketch.add_bind_distance((&a, &b)))
+        .unwrap();
+    assert_undone(&mut history, &before);
+
+    // A rejected binding records nothing
+    assert!(history
+        .add_bind(|sketch| sketch.add_bind_distance((&a, &b)))
+        .is_err());
+    assert_eq!(history.undo_label(), Some("add binding"));
+}
+
+#[test]
+fn edits_are_undone() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 0.));
+    let c = sketch.add_vertex(Point::new(1., 3.));
+    sketch.add_line(&a, &b);
+    sketch.add_bind_fixed(&a).unwrap();
+    let base = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    let side = sketch.add_bind_distance((&b, &c)).unwrap().id;
+    sketch.set_parameter("w", "3").unwrap();
+    sketch.set_binding_expr(&side, "w + 1").unwrap();
+    let mut history = History::new(sketch);
+
+    let before = json(&history);
+    assert!(history.set_enabled(&base, false));
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    history.set_reference(&base, true).unwrap();
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    assert!(history.set_strength(&base, Strength::Weight(2.)));
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    history.set_parameter("w", "5").unwrap();
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    history.set_parameter("h", "2").unwrap();
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    history.set_binding_expr(&base, "2 * w").unwrap();
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    assert!(history.clear_binding_expr(&side));
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    assert!(history.set_value(&side, 7.));
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    let bounds = Some(Rect::new(-1., -1., 1., 1.));
+    assert!(history.set_vertex_bounds(&a.id, bounds));
+    assert_undone(&mut history, &before);
+
+    // The fixed position of a point driven by expressions
+    let fixed = history
+        .add_bind(|sketch| sketch.add_bind_fixed(&b))
+        .unwrap();
+    let before = json(&history);
+    history.set_binding_expr(&fixed.id, "(w, 0)").unwrap();
+    assert_undone(&mut history, &before);
+
+    // Last, the bindings on the vertex are removed with it
+    let before = json(&history);
+    history.remove_vertex(&c.id, true).unwrap();
+    assert_undone(&mut history, &before);
+
+    let before = json(&history);
+    let shape = *history.sketch().shapes().keys().next().unwrap();
+    history.remove_shape(&shape).unwrap();
+    assert_undone(&mut history, &before);
+}
+
+#[test]
+fn groups_are_undone_at_once() {
+    let mut history = History::new(Sketch::new());
+    let before = json(&history);
+    history.begin_group("segment");
+    let a = history.add_vertex(Point::new(0., 0.));
+    history.begin_group("end");
+    let b = history.add_vertex(Point::new(4., 0.));
+    history.add_line(&a, &b);
+    history.end_group();
+    // Still in the outer group
+    assert_eq!(history.undo_label(), None);
+    history
+        .add_bind(|sketch| sketch.add_bind_horizontal((&a, &b)))
+        .unwrap();
+    history.end_group();
+    assert_eq!(history.undo_label(), Some("segment"));
+    assert_undone(&mut history, &before);
+    assert!(history.undo().unwrap());
+    assert!(!history.undo().unwrap());
+
+    // An empty group is no step
+    history.begin_group("nothing");
+    history.end_group();
+    assert_eq!(history.redo_label(), Some("segment"));
+    assert!(!history.undo().unwrap());
+}
+
+// The moves of a drag are merged into one by vertex, its solves included, and
+// the drag is undone by one step
+#[test]
+fn drag_is_undone_at_once() {
+    let mut sketch = Sketch::new();
+    let b = sketch.add_vertex(Point::new(4., 0.));
+    let c = sketch.add_vertex(Point::new(10., 0.));
+    sketch.add_bind_fixed_y(&b).unwrap();
+    let mut history = History::new(sketch);
+    let opts = SolveOptions::default();
+    let before = json(&history);
+
+    history.begin_group("drag");
+    for step in 1..=20 {
+        let step = step as f64;
+        history.move_vertex(&b.id, Point::new(4. + step * 0.1, step * 0.05));
+        history.solve_incremental(&opts).unwrap();
+    }
+    history.end_group();
+    assert_eq!(moves(&history), 1);
+    assert!((pt(&history, &b) - Point::new(6., 0.)).hypot() < 1e-6);
+    assert_eq!(pt(&history, &c), Point::new(10., 0.));
+
+    let after = json(&history);
+    assert!(history.undo().unwrap());
+    assert_eq!(json(&history), before);
+    assert!(!history.undo().unwrap());
+    assert!(history.redo().unwrap());
+    assert_eq!(json(&history), after);
+}
+
+#[test]
+fn solve_records_the_changes() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 0.));
+    let d = sketch.add_vertex(Point::new(10., 0.));
+    let e = sketch.add_vertex(Point::new(13., 1.));
+    sketch.add_bind_fixed(&a).unwrap();
+    let length = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_reference(&length, true).unwrap();
+    sketch.add_bind_horizontal((&d, &e)).unwrap();
+    let opts = SolveOptions::default();
+    sketch.solve(&opts).unwrap();
+    let mut history = History::new(sketch);
+    let (d_pt, e_pt) = (pt(&history, &d), pt(&history, &e));
+    history.move_vertex(&b.id, Point::new(3., 1.));
+    let before = json(&history);
+    history.solve_incremental(&opts).unwrap();
+
+    // The segment isn't solved again
+    let changes = history.sketch().solve_changes();
+    assert!(!changes.vertices.contains_key(&d.id));
+    assert!(!changes.vertices.contains_key(&e.id));
+    assert!(changes.values.contains_key(&length));
+    assert_eq!(pt(&history, &d), d_pt);
+    assert_eq!(pt(&history, &e), e_pt);
+
+    assert_undone(&mut history, &before);
+}
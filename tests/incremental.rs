@@ -0,0 +1,122 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::math::{BindingId, Vertex};
+use test_gomez::sketch::Sketch;
+
+// Two components: a triangle pinned by a vertex and a segment pinned by a vertex
+struct Fixture {
+    sketch: Sketch,
+    triangle: [Vertex; 3],
+    segment: [Vertex; 2],
+    side: BindingId,
+}
+
+fn fixture() -> Fixture {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 0.5));
+    let c = sketch.add_vertex(Point::new(1., 3.));
+    sketch.add_bind_fixed(&a).unwrap();
+    let side = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.add_bind_distance((&b, &c)).unwrap();
+    sketch.add_bind_distance((&c, &a)).unwrap();
+    let d = sketch.add_vertex(Point::new(10., 0.));
+    let e = sketch.add_vertex(Point::new(13., 1.));
+    sketch.add_bind_fixed(&d).unwrap();
+    sketch.add_bind_horizontal((&d, &e)).unwrap();
+    sketch.solve(&SolveOptions::default()).unwrap();
+    Fixture {
+        sketch,
+        triangle: [a, b, c],
+        segment: [d, e],
+        side,
+    }
+}
+
+fn pt(sketch: &Sketch, v: &Vertex) -> Point {
+    sketch.vertex(&v.id).unwrap().pt
+}
+
+fn assert_same(incremental: &Sketch, full: &Sketch) {
+    incremental.vertices().values().for_each(|v| {
+        let other = full.vertex(&v.id).unwrap().pt;
+        assert!((v.pt - other).hypot() < 1e-6, "{:?} != {:?}", v.pt, other);
+    });
+}
+
+#[test]
+fn drag_matches_full_solve() {
+    let Fixture {
+        mut sketch,
+        triangle: [_, _, c],
+        ..
+    } = fixture();
+    let opts = SolveOptions::default();
+    for step in 0..10 {
+        let target = pt(&sketch, &c) + (0.1, 0.05 * step as f64);
+        sketch.move_vertex(&c.id, target);
+        let mut full = sketch.clone();
+        sketch.solve_incremental(&opts).unwrap();
+        full.solve(&opts).unwrap();
+        assert_same(&sketch, &full);
+    }
+}
+
+#[test]
+fn clean_component_does_not_move() {
+    let Fixture {
+        mut sketch,
+        segment: [d, e],
+        side,
+        ..
+    } = fixture();
+    let (d_pt, e_pt) = (pt(&sketch, &d), pt(&sketch, &e));
+    assert!(sketch.set_binding_value(&side, 5.));
+    sketch.solve_incremental(&SolveOptions::default()).unwrap();
+    assert_eq!(pt(&sketch, &d), d_pt);
+    assert_eq!(pt(&sketch, &e), e_pt);
+}
+
+#[test]
+fn bindings_edits_are_solved() {
+    let Fixture {
+        mut sketch,
+        triangle: [a, b, _],
+        segment: [d, e],
+        side,
+    } = fixture();
+    let opts = SolveOptions::default();
+    // Solve once so the components keep their layouts
+    sketch.move_vertex(&e.id, Point::new(14., 2.));
+    sketch.solve_incremental(&opts).unwrap();
+
+    // A binding between vertices of the same component
+    let length = sketch.add_bind_distance((&d, &e)).unwrap().id;
+    assert!(sketch.set_binding_value(&length, 2.));
+    sketch.solve_incremental(&opts).unwrap();
+    assert!(((pt(&sketch, &e) - pt(&sketch, &d)).hypot() - 2.).abs() < 1e-6);
+
+    // A binding removed, the free end stays where it is dragged
+    sketch.remove_binding(&length).unwrap();
+    sketch.move_vertex(&e.id, Point::new(20., 0.));
+    sketch.solve_incremental(&opts).unwrap();
+    assert!((pt(&sketch, &e) - Point::new(20., 0.)).hypot() < 1e-6);
+
+    // A binding disabled
+    let before = (pt(&sketch, &b) - pt(&sketch, &a)).hypot();
+    assert!(sketch.set_enabled(&side, false));
+    sketch.move_vertex(&b.id, Point::new(6., 0.));
+    let mut full = sketch.clone();
+    sketch.solve_incremental(&opts).unwrap();
+    full.solve(&opts).unwrap();
+    assert_same(&sketch, &full);
+    assert!(((pt(&sketch, &b) - pt(&sketch, &a)).hypot() - before).abs() > 0.1);
+
+    // A line merging the components
+    sketch.add_line(&b, &d);
+    sketch.move_vertex(&e.id, Point::new(15., 3.));
+    let mut full = sketch.clone();
+    sketch.solve_incremental(&opts).unwrap();
+    full.solve(&opts).unwrap();
+    assert_same(&sketch, &full);
+}
@@ -0,0 +1,40 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::math::Strength;
+use test_gomez::sketch::Sketch;
+
+fn pt(sketch: &Sketch, id: usize) -> Point {
+    sketch.vertices().values().nth(id).unwrap().pt
+}
+
+// Without a required binding there is no required equation to correct the steps
+// on, which used to panic on the SVD of an empty jacobian
+#[test]
+fn soft_bindings_only() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(3., 0.));
+    let id = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&id, 5.);
+    sketch.set_strength(&id, Strength::Weak);
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert!((pt(&sketch, 0).distance(pt(&sketch, 1)) - 5.).abs() < 1e-6);
+}
+
+#[test]
+fn distance_range_is_clamped() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(5., 0.));
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_horizontal((&a, &b)).unwrap();
+    sketch.add_bind_distance_range((&a, &b), 1., 2.).unwrap();
+    let opts = SolveOptions::default();
+    sketch.solve(&opts).unwrap();
+    assert!((pt(&sketch, 1) - Point::new(2., 0.)).hypot() < 1e-6);
+
+    // Within the range nothing moves
+    sketch.move_vertex(&b.id, Point::new(1.5, 0.));
+    sketch.solve(&opts).unwrap();
+    assert!((pt(&sketch, 1) - Point::new(1.5, 0.)).hypot() < 1e-6);
+}
@@ -0,0 +1,97 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::inference::InferenceTolerances;
+use test_gomez::math::Binding;
+use test_gomez::sketch::Sketch;
+
+// Sketch of the lines between the points
+fn lines(segments: &[(Point, Point)]) -> Sketch {
+    let mut sketch = Sketch::new();
+    for (p0, p1) in segments {
+        let va = sketch.add_vertex(*p0);
+        let vb = sketch.add_vertex(*p1);
+        sketch.add_line(&va, &vb);
+    }
+    sketch
+}
+
+fn kinds(sketch: &Sketch) -> Vec<&'static str> {
+    let candidates = sketch
+        .infer_bindings(&InferenceTolerances::default())
+        .unwrap();
+    candidates
+        .iter()
+        .map(|candidate| match candidate.binding {
+            Binding::Horizontal(_) => "horizontal",
+            Binding::Vertical(_) => "vertical",
+            Binding::Parallel(_) => "parallel",
+            Binding::Perpendicular(_) => "perpendicular",
+            Binding::EqualLength(_) => "equal length",
+            Binding::Coincident(_) => "coincident",
+            _ => "other",
+        })
+        .collect()
+}
+
+#[test]
+fn horizontal_and_vertical() {
+    let pt = Point::new;
+    // 0.6 degree off the axes, inside the 2 degrees
+    let sketch = lines(&[(pt(0., 0.), pt(10., 0.1)), (pt(20., 0.), pt(20.1, 10.))]);
+    let found = kinds(&sketch);
+    assert!(found.contains(&"horizontal"), "{found:?}");
+    assert!(found.contains(&"vertical"), "{found:?}");
+
+    // 5.7 degrees off, outside
+    let sketch = lines(&[(pt(0., 0.), pt(10., 1.)), (pt(20., 0.), pt(21., 10.))]);
+    let found = kinds(&sketch);
+    assert!(!found.contains(&"horizontal"), "{found:?}");
+    assert!(!found.contains(&"vertical"), "{found:?}");
+}
+
+#[test]
+fn parallel() {
+    let at = |degrees: f64, origin: Point| {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        (origin, origin + (10. * cos, 10. * sin))
+    };
+    let sketch = lines(&[at(30., Point::ZERO), at(31., Point::new(0., 20.))]);
+    assert!(kinds(&sketch).contains(&"parallel"));
+
+    let sketch = lines(&[at(30., Point::ZERO), at(40., Point::new(0., 20.))]);
+    assert!(!kinds(&sketch).contains(&"parallel"));
+}
+
+#[test]
+fn coincident() {
+    let pt = Point::new;
+    let sketch = lines(&[(pt(0., 0.), pt(10., 3.)), (pt(10.5, 3.), pt(14., 9.))]);
+    assert!(kinds(&sketch).contains(&"coincident"));
+
+    let sketch = lines(&[(pt(0., 0.), pt(10., 3.)), (pt(12., 3.), pt(14., 9.))]);
+    assert!(!kinds(&sketch).contains(&"coincident"));
+}
+
+// A hand drawn rectangle gives many dependent relations (4 right angles, 2 pairs
+// of parallel sides, horizontal and vertical sides), only independent ones are kept
+#[test]
+fn candidates_never_over_constrain() {
+    let pt = Point::new;
+    let corners = [pt(0., 0.), pt(10., 0.1), pt(10.1, 5.), pt(-0.1, 5.05)];
+    let mut sketch = Sketch::new();
+    let vertices: Vec<_> = corners.iter().map(|c| sketch.add_vertex(*c)).collect();
+    for idx in 0..4 {
+        sketch.add_line(&vertices[idx], &vertices[(idx + 1) % 4]);
+    }
+    let candidates = sketch
+        .infer_bindings(&InferenceTolerances::default())
+        .unwrap();
+    assert!(candidates.len() >= 3, "{candidates:?}");
+    for candidate in candidates {
+        sketch.add_binding(candidate.binding).unwrap();
+    }
+    // The dependencies show at the solution, where the relations hold
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let analysis = sketch.dof_analysis().unwrap();
+    assert!(!analysis.is_over_constrained(), "{analysis:?}");
+}
@@ -0,0 +1,39 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::math::{BindingId, Vertex};
+use test_gomez::sketch::Sketch;
+
+// b at 5 from the fixed a on the horizontal line through a, and its y fixed too:
+// 5 equations on 4 coordinates
+fn redundant() -> (Sketch, Vertex, BindingId) {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(3., 1.));
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_horizontal((&a, &b)).unwrap();
+    let length = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&length, 5.);
+    let fixed_y = sketch.add_bind_fixed_y(&b).unwrap().id;
+    sketch.set_binding_value(&fixed_y, 0.);
+    (sketch, b, fixed_y)
+}
+
+#[test]
+fn consistent_redundant_bindings_converge() {
+    let (mut sketch, b, _) = redundant();
+    let analysis = sketch.dof_analysis().unwrap();
+    assert_eq!(analysis.redundant(), 1, "{analysis:?}");
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let pt = sketch.vertex(&b.id).unwrap().pt;
+    assert!((pt - Point::new(5., 0.)).hypot() < 1e-6, "{pt:?}");
+}
+
+#[test]
+fn contradictory_bindings_fail() {
+    let (mut sketch, b, fixed_y) = redundant();
+    sketch.set_binding_value(&fixed_y, 2.);
+    let before = sketch.vertex(&b.id).unwrap().pt;
+    assert!(sketch.solve(&SolveOptions::default()).is_err());
+    // The vertices only move to a solution
+    assert_eq!(sketch.vertex(&b.id).unwrap().pt, before);
+}
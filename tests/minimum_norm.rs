@@ -0,0 +1,47 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::sketch::Sketch;
+
+// Nothing holds the segment but the horizontal binding, the least move of the
+// vertices brings both to the mean of their y
+#[test]
+fn least_move_is_taken() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 2.));
+    sketch.add_bind_horizontal((&a, &b)).unwrap();
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let pt = |v_id| sketch.vertex(v_id).unwrap().pt;
+    assert!(
+        (pt(&a.id) - Point::new(0., 1.)).hypot() < 1e-9,
+        "{:?}",
+        pt(&a.id)
+    );
+    assert!(
+        (pt(&b.id) - Point::new(4., 1.)).hypot() < 1e-9,
+        "{:?}",
+        pt(&b.id)
+    );
+}
+
+// Stretched along the segment, by half the change at each end
+#[test]
+fn stretch_is_shared() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(3., 0.));
+    let id = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&id, 5.);
+    sketch.solve(&SolveOptions::default()).unwrap();
+    let pt = |v_id| sketch.vertex(v_id).unwrap().pt;
+    assert!(
+        (pt(&a.id) - Point::new(-1., 0.)).hypot() < 1e-6,
+        "{:?}",
+        pt(&a.id)
+    );
+    assert!(
+        (pt(&b.id) - Point::new(4., 0.)).hypot() < 1e-6,
+        "{:?}",
+        pt(&b.id)
+    );
+}
@@ -0,0 +1,73 @@
+use kurbo::Point;
+use test_gomez::document;
+use test_gomez::sketch::Sketch;
+
+fn ids(sketch: &Sketch) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    (
+        sketch.vertices().keys().map(|id| **id).collect(),
+        sketch.shapes().keys().map(|id| **id).collect(),
+        sketch.bindings().keys().map(|id| **id).collect(),
+    )
+}
+
+// Three vertices, two lines and two bindings
+fn sketch() -> Sketch {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 0.));
+    let c = sketch.add_vertex(Point::new(4., 3.));
+    sketch.add_line(&a, &b);
+    sketch.add_line(&b, &c);
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_vertical((&b, &c)).unwrap();
+    sketch
+}
+
+#[test]
+fn ids_are_deterministic() {
+    assert_eq!(ids(&sketch()), ids(&sketch()));
+    assert_eq!(ids(&sketch()), (vec![0, 1, 2], vec![0, 1], vec![0, 1]));
+}
+
+#[test]
+fn ids_are_never_reused() {
+    let mut sketch = sketch();
+    let c = *sketch.vertices().keys().next_back().unwrap();
+    let removed = sketch.remove_vertex(&c, true).unwrap();
+    let d = sketch.add_vertex(Point::new(1., 1.));
+    assert_eq!(*d.id, 3);
+    let a = *sketch.vertices().values().next().unwrap();
+    let bind = sketch.add_bind_fixed(&d).unwrap();
+    assert_eq!(*bind.id, 2);
+    let line = sketch.add_line(&a, &d);
+    assert_eq!(*line.id, 2);
+
+    // The removed ones come back under their ids, an id never given is refused
+    sketch.restore_vertex(removed.vertex).unwrap();
+    removed
+        .shapes
+        .into_iter()
+        .for_each(|shape| sketch.restore_shape(shape).unwrap());
+    removed
+        .bindings
+        .into_iter()
+        .for_each(|bind| sketch.restore_binding(bind).unwrap());
+    assert_eq!(
+        ids(&sketch),
+        (vec![0, 1, 2, 3], vec![0, 1, 2], vec![0, 1, 2])
+    );
+    let mut never = d;
+    *never.id = 4;
+    assert!(sketch.restore_vertex(never).is_err());
+    assert_eq!(*sketch.add_vertex(Point::ZERO).id, 4);
+}
+
+// A document with a stale counter still gives new ids after the ones it holds
+#[test]
+fn stale_counter_is_skipped() {
+    let json = document::to_json(&sketch()).unwrap();
+    let stale = json.replace("\"next_id\": 3", "\"next_id\": 0");
+    assert_ne!(stale, json);
+    let mut sketch = document::from_json(&stale).unwrap();
+    assert_eq!(*sketch.add_vertex(Point::ZERO).id, 3);
+}
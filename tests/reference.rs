@@ -0,0 +1,38 @@
+use kurbo::Point;
+use test_gomez::dsl;
+use test_gomez::math::BindingError;
+use test_gomez::sketch::Sketch;
+
+#[test]
+fn only_dimensions_are_references() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(3., 4.));
+    let c = sketch.add_vertex(Point::new(0., 1.));
+    let d = sketch.add_vertex(Point::new(3., 5.));
+    let distance = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    let parallel = sketch.add_bind_parallel((&a, &b), (&c, &d)).unwrap().id;
+
+    assert_eq!(
+        sketch.set_reference(&parallel, true),
+        Err(BindingError::NotMeasurable(parallel))
+    );
+    assert!(!sketch.bindings()[&parallel].get_props().reference);
+    assert_eq!(sketch.set_reference(&parallel, false), Ok(()));
+
+    assert!(sketch.set_binding_value(&distance, 10.));
+    assert_eq!(sketch.set_reference(&distance, true), Ok(()));
+    assert_eq!(sketch.bindings()[&distance].value(), Some(5.));
+}
+
+#[test]
+fn text_reference_needs_a_dimension() {
+    let text = "\
+point a = (0, 0)
+point b = (3, 4)
+reference vertical (a, b)
+";
+    let err = dsl::parse(text).unwrap_err();
+    assert_eq!((err.line, err.column), (3, 1));
+    assert!(err.message.contains("no dimension"), "{err}");
+}
@@ -0,0 +1,61 @@
+use kurbo::Point;
+use test_gomez::math::{RemoveError, Vertex};
+use test_gomez::sketch::Sketch;
+
+// A line a-b with a fixed a and a horizontal a-b, c is bound to b alone
+fn sketch() -> (Sketch, [Vertex; 3]) {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(4., 0.));
+    let c = sketch.add_vertex(Point::new(4., 3.));
+    sketch.add_line(&a, &b);
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_horizontal((&a, &b)).unwrap();
+    sketch.add_bind_vertical((&b, &c)).unwrap();
+    (sketch, [a, b, c])
+}
+
+#[test]
+fn removal_errors() {
+    let (mut sketch, [a, _, c]) = sketch();
+    let mut unknown = c;
+    *unknown.id = 9;
+    assert!(matches!(
+        sketch.remove_vertex(&unknown.id, true),
+        Err(RemoveError::UnknownVertex(v_id)) if v_id == unknown.id
+    ));
+    let line = *sketch.shapes().keys().next().unwrap();
+    assert!(matches!(
+        sketch.remove_vertex(&a.id, false),
+        Err(RemoveError::UsedByShapes(ids)) if ids == [line]
+    ));
+    let vertical = *sketch.bindings().keys().next_back().unwrap();
+    assert!(matches!(
+        sketch.remove_vertex(&c.id, false),
+        Err(RemoveError::HasDependents(ids)) if ids == [vertical]
+    ));
+    // Nothing was removed
+    assert_eq!(sketch.vertices().len(), 3);
+    assert_eq!(sketch.shapes().len(), 1);
+    assert_eq!(sketch.bindings().len(), 3);
+}
+
+#[test]
+fn removal_cascades() {
+    let (mut sketch, [a, b, c]) = sketch();
+    let removed = sketch.remove_vertex(&a.id, true).unwrap();
+    assert_eq!(removed.vertex.id, a.id);
+    assert_eq!(removed.shapes.len(), 1);
+    assert_eq!(removed.bindings.len(), 2);
+    assert!(removed.bindings.iter().all(|bind| bind.uses_vertex(&a.id)));
+    assert!(sketch.shapes().is_empty());
+    assert_eq!(sketch.bindings().len(), 1);
+    assert!(sketch.bindings().validate(sketch.vertices()).is_ok());
+
+    // Without bindings or shapes left, no cascade is needed
+    sketch.remove_vertex(&c.id, true).unwrap();
+    sketch.remove_vertex(&b.id, false).unwrap();
+    assert!(sketch.vertices().is_empty());
+    assert!(sketch.bindings().is_empty());
+    assert!(sketch.bindings().validate(sketch.vertices()).is_ok());
+}
@@ -0,0 +1,66 @@
+use kurbo::Point;
+use test_gomez::bindings::SolveOptions;
+use test_gomez::math::{Strength, Vertex};
+use test_gomez::sketch::Sketch;
+
+// A vertex b on the horizontal line through the fixed origin a
+fn horizontal() -> (Sketch, Vertex, Vertex) {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(2., 0.));
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_horizontal((&a, &b)).unwrap();
+    (sketch, a, b)
+}
+
+fn x(sketch: &Sketch, v: &Vertex) -> f64 {
+    sketch.vertex(&v.id).unwrap().pt.x
+}
+
+#[test]
+fn required_bindings_are_met() {
+    let (mut sketch, a, b) = horizontal();
+    let fixed = sketch.add_bind_fixed_x(&b).unwrap().id;
+    sketch.set_binding_value(&fixed, 3.);
+    let length = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&length, 5.);
+    sketch.set_strength(&length, Strength::Strong);
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert!((x(&sketch, &b) - 3.).abs() < 1e-9);
+}
+
+#[test]
+fn weak_gives_way_to_strong() {
+    let (mut sketch, a, b) = horizontal();
+    let fixed = sketch.add_bind_fixed_x(&b).unwrap().id;
+    sketch.set_binding_value(&fixed, 4.);
+    sketch.set_strength(&fixed, Strength::Strong);
+    let length = sketch.add_bind_distance((&a, &b)).unwrap().id;
+    sketch.set_binding_value(&length, 6.);
+    sketch.set_strength(&length, Strength::Weak);
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert!((x(&sketch, &b) - 4.).abs() < 0.1, "{}", x(&sketch, &b));
+
+    // The other way round
+    sketch.set_strength(&fixed, Strength::Weak);
+    sketch.set_strength(&length, Strength::Strong);
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert!((x(&sketch, &b) - 6.).abs() < 0.1, "{}", x(&sketch, &b));
+}
+
+// The weighted squared violations 3 (x - 10)^2 + x^2 are the least at x = 7.5
+#[test]
+fn weights_are_honoured() {
+    let mut sketch = Sketch::new();
+    let b = sketch.add_vertex(Point::new(2., 0.));
+    let c = sketch.add_vertex(Point::new(10., 0.));
+    sketch.add_bind_fixed(&c).unwrap();
+    sketch.add_bind_fixed_y(&b).unwrap();
+    let coincident = sketch.add_bind_coincident(&b, &c).unwrap().id;
+    sketch.set_strength(&coincident, Strength::Weight(3.));
+    let fixed = sketch.add_bind_fixed_x(&b).unwrap().id;
+    sketch.set_binding_value(&fixed, 0.);
+    sketch.set_strength(&fixed, Strength::Weight(1.));
+    sketch.solve(&SolveOptions::default()).unwrap();
+    assert!((x(&sketch, &b) - 7.5).abs() < 1e-4, "{}", x(&sketch, &b));
+}
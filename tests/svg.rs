@@ -0,0 +1,118 @@
+use kurbo::Point;
+use test_gomez::import::ImportOptions;
+use test_gomez::sketch::Sketch;
+use test_gomez::svg::{self, SvgOptions};
+
+fn points(sketch: &Sketch) -> Vec<Point> {
+    sketch.vertices().values().map(|v| v.pt).collect()
+}
+
+fn assert_points(sketch: &Sketch, expected: &[Point]) {
+    let pts = points(sketch);
+    assert_eq!(pts.len(), expected.len(), "{pts:?}");
+    expected.iter().for_each(|e| {
+        assert!(
+            pts.iter().any(|pt| (*pt - *e).hypot() < 1e-9),
+            "{e:?} not in {pts:?}"
+        );
+    });
+}
+
+#[test]
+fn doctype_is_accepted() {
+    let text = r#"<?xml version="1.0"?>
+<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd">
+<svg xmlns="http://www.w3.org/2000/svg"><line x1="0" y1="0" x2="3" y2="4"/></svg>"#;
+    let sketch = svg::import(text, &ImportOptions::default()).unwrap();
+    assert_points(&sketch, &[Point::new(0., 0.), Point::new(3., 4.)]);
+}
+
+#[test]
+fn transforms_are_applied() {
+    let text = r#"<svg xmlns="http://www.w3.org/2000/svg">
+  <g transform="translate(10, 20)">
+    <line transform="scale(2) rotate(90)" x1="0" y1="0" x2="1" y2="0"/>
+    <polyline transform="matrix(1 0 0 1 -10 -20)" points="1,1 2,1"/>
+  </g>
+</svg>"#;
+    let sketch = svg::import(text, &ImportOptions::default()).unwrap();
+    assert_points(
+        &sketch,
+        &[
+            Point::new(10., 20.),
+            Point::new(10., 22.),
+            Point::new(1., 1.),
+            Point::new(2., 1.),
+        ],
+    );
+
+    let text = r#"<svg><line transform="perspective(2)" x2="1"/></svg>"#;
+    let err = svg::import(text, &ImportOptions::default()).unwrap_err();
+    assert!(err.contains("invalid transform"), "{err}");
+}
+
+// The elements of the group of the given id, by tag name
+fn group<'a>(doc: &'a roxmltree::Document, id: &str) -> Vec<roxmltree::Node<'a, 'a>> {
+    let group = doc
+        .descendants()
+        .find(|node| node.attribute("id") == Some(id))
+        .unwrap();
+    group
+        .descendants()
+        .skip(1)
+        .filter(|n| n.is_element())
+        .collect()
+}
+
+fn count(nodes: &[roxmltree::Node], tag: &str) -> usize {
+    nodes.iter().filter(|n| n.has_tag_name(tag)).count()
+}
+
+#[test]
+fn glyphs_are_drawn() {
+    let mut sketch = Sketch::new();
+    let a = sketch.add_vertex(Point::new(0., 0.));
+    let b = sketch.add_vertex(Point::new(3., 4.));
+    let c = sketch.add_vertex(Point::new(6., 0.));
+    sketch.add_line(&a, &b);
+    sketch.add_line(&b, &c);
+    sketch.add_bind_fixed(&a).unwrap();
+    sketch.add_bind_distance((&a, &b)).unwrap();
+    let text = svg::export(&sketch, &SvgOptions::default());
+    let doc = roxmltree::Document::parse(&text).unwrap();
+    assert_eq!(count(&group(&doc, "shapes"), "path"), 2);
+    assert_eq!(count(&group(&doc, "vertices"), "circle"), 3);
+    // The anchor: 3 sides and 4 hatches. The dimension: 2 extension lines, the
+    // dimension line and 2 arrows of 2 lines, and its value
+    let glyphs = group(&doc, "bindings");
+    assert_eq!(count(&glyphs, "line"), 7 + 7, "{text}");
+    let texts: Vec<_> = glyphs
+        .iter()
+        .filter(|n| n.has_tag_name("text"))
+        .map(|n| n.text().unwrap())
+        .collect();
+    assert_eq!(texts, ["5.00"]);
+
+    // The ranges draw nothing, disabled bindings are faded
+    sketch.add_bind_distance_range((&b, &c), 1., 2.).unwrap();
+    let horizontal = sketch.add_bind_horizontal((&a, &c)).unwrap().id;
+    sketch.set_enabled(&horizontal, false);
+    let text = svg::export(&sketch, &SvgOptions::default());
+    let doc = roxmltree::Document::parse(&text).unwrap();
+    let glyphs = group(&doc, "bindings");
+    assert_eq!(count(&glyphs, "line"), 7 + 7, "{text}");
+    let faded: Vec<_> = glyphs
+        .iter()
+        .filter(|n| n.attribute("opacity").is_some())
+        .flat_map(|n| n.descendants().filter(|n| n.has_tag_name("text")))
+        .map(|n| n.text().unwrap())
+        .collect();
+    assert_eq!(faded, ["H"]);
+
+    let opts = SvgOptions {
+        annotate: false,
+        ..SvgOptions::default()
+    };
+    let text = svg::export(&sketch, &opts);
+    assert!(!text.contains("id=\"bindings\""), "{text}");
+}